@@ -0,0 +1,67 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use futures_util::future::BoxFuture;
+use tokio::runtime::Runtime;
+use webmq::{
+    core::{errors::WebMQError, models::message::Message, traits::MessagingDispatcher},
+    data::memory_queue::MemoryQueue,
+    messaging::base_dispatcher::{BaseMessagingDispatcher, BoxedQueue},
+};
+
+const MESSAGES: usize = 4096;
+const MESSAGE_SIZE: usize = 256;
+const QUEUE_COUNTS: [usize; 4] = [1, 2, 4, 8];
+
+async fn create_memory_queue() -> Result<BoxedQueue, WebMQError> {
+    Ok(Box::new(MemoryQueue::new()))
+}
+
+/// Publishes, consumes and acknowledges the same number of messages in total,
+/// spread over a growing number of queues with one worker each.
+async fn round_trips(dispatcher: Arc<BaseMessagingDispatcher>, queue_count: usize) {
+    let per_queue = MESSAGES / queue_count;
+    let workers: Vec<_> = (0..queue_count)
+        .map(|index| {
+            let dispatcher = dispatcher.clone();
+            tokio::task::spawn(async move {
+                let queue = format!("bench-{index}");
+                for _ in 0..per_queue {
+                    dispatcher.publish(queue.clone(), Message::new(vec![0; MESSAGE_SIZE])).await;
+                    let delivery = dispatcher
+                        .consume(queue.clone(), Duration::from_secs(30))
+                        .await
+                        .expect("message was just published");
+                    dispatcher.ack(queue.clone(), delivery.receipt).await;
+                }
+            })
+        })
+        .collect();
+
+    for worker in workers {
+        worker.await.expect("worker panicked");
+    }
+}
+
+fn dispatcher_throughput(c: &mut Criterion) {
+    let runtime = Runtime::new().expect("Failed to create runtime");
+    let mut group = c.benchmark_group("dispatcher_round_trips");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+
+    for queue_count in QUEUE_COUNTS {
+        group.bench_with_input(BenchmarkId::from_parameter(queue_count), &queue_count, |b, &queue_count| {
+            b.to_async(&runtime).iter(|| {
+                let dispatcher = Arc::new(BaseMessagingDispatcher::new(
+                    Box::pin(|_: &str| Box::pin(create_memory_queue()) as BoxFuture<_>),
+                    HashMap::new(),
+                ));
+                round_trips(dispatcher, queue_count)
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, dispatcher_throughput);
+criterion_main!(benches);
@@ -0,0 +1,179 @@
+use std::collections::BTreeMap;
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use hyper::{HeaderMap, header};
+use serde::Deserialize;
+
+use crate::core::models::message::Message;
+
+use super::metadata::{requested_ttl, user_header_name, user_headers};
+
+const NDJSON: &str = "application/x-ndjson";
+const OCTET_STREAM: &str = "application/octet-stream";
+const JSON: &str = "application/json";
+
+const LENGTH_PREFIX_SIZE: usize = 4;
+
+/// How the messages in a batch are laid out in the request body.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BatchFormat {
+    /// `application/x-ndjson`: one JSON document per line.
+    Lines,
+    /// `application/octet-stream`: every message preceded by its length as a
+    /// big endian `u32`.
+    LengthPrefixed,
+    /// `application/json`: an array of [`BatchEntry`] objects.
+    Json,
+}
+
+/// A single message in a JSON batch.
+#[derive(Deserialize)]
+struct BatchEntry {
+    /// Base64 encoded message body.
+    data: String,
+    content_type: Option<String>,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+}
+
+impl BatchFormat {
+    /// Picks the format by the request's content type.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let content_type = headers.get(header::CONTENT_TYPE)?.to_str().ok()?;
+        let essence = content_type.split(';').next()?.trim();
+        if essence.eq_ignore_ascii_case(NDJSON) {
+            Some(Self::Lines)
+        } else if essence.eq_ignore_ascii_case(OCTET_STREAM) {
+            Some(Self::LengthPrefixed)
+        } else if essence.eq_ignore_ascii_case(JSON) {
+            Some(Self::Json)
+        } else {
+            None
+        }
+    }
+
+    /// Splits `body` into messages. The TTL and `X-WebMQ-*` headers on the
+    /// request are set on every message, unless a JSON entry sets the same
+    /// header itself.
+    pub fn parse(self, headers: &HeaderMap, body: &[u8]) -> Result<Vec<Message>, String> {
+        let shared = user_headers(headers);
+        let ttl = requested_ttl(headers)?;
+        let message = |data: Vec<u8>, content_type: Option<&str>| {
+            let mut message = Message {
+                content_type: content_type.map(str::to_string),
+                headers: shared.clone(),
+                ..Message::new(data)
+            };
+            if let Some(ttl) = ttl {
+                message.expire_after(ttl);
+            }
+            message
+        };
+
+        match self {
+            Self::Lines => Ok(body
+                .split(|byte| *byte == b'\n')
+                .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+                .filter(|line| !line.trim_ascii().is_empty())
+                .map(|line| message(line.to_vec(), Some(JSON)))
+                .collect()),
+            Self::LengthPrefixed => {
+                let mut messages = vec![];
+                let mut rest = body;
+                while !rest.is_empty() {
+                    let Some((length, tail)) = rest.split_first_chunk::<LENGTH_PREFIX_SIZE>() else {
+                        return Err("Truncated length prefix".to_string());
+                    };
+                    let length = u32::from_be_bytes(*length) as usize;
+                    if tail.len() < length {
+                        return Err(format!("Message {} is truncated", messages.len()));
+                    }
+
+                    let (data, tail) = tail.split_at(length);
+                    messages.push(message(data.to_vec(), None));
+                    rest = tail;
+                }
+                Ok(messages)
+            }
+            Self::Json => {
+                let entries: Vec<BatchEntry> =
+                    serde_json::from_slice(body).map_err(|e| format!("Invalid batch: {e}"))?;
+
+                let mut messages = Vec::with_capacity(entries.len());
+                for (index, entry) in entries.into_iter().enumerate() {
+                    let data = BASE64_STANDARD
+                        .decode(entry.data)
+                        .map_err(|e| format!("Invalid data in message {index}: {e}"))?;
+                    if let Some(name) = entry.headers.keys().find(|name| user_header_name(name).is_none()) {
+                        return Err(format!("Invalid header name {name} in message {index}"));
+                    }
+
+                    let mut message = message(data, entry.content_type.as_deref());
+                    message.headers.extend(entry.headers);
+                    messages.push(message);
+                }
+                Ok(messages)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::header::HeaderValue;
+
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn splits_lines_skipping_blank_ones() {
+        let messages = BatchFormat::Lines
+            .parse(&HeaderMap::new(), b"{\"a\":1}\r\n\n  \n{\"b\":2}")
+            .unwrap();
+        let data: Vec<&[u8]> = messages.iter().map(|message| message.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"{\"a\":1}"[..], &b"{\"b\":2}"[..]]);
+        assert_eq!(messages[0].content_type.as_deref(), Some(JSON));
+    }
+
+    #[test]
+    fn splits_length_prefixed_messages() {
+        let body = [&[0, 0, 0, 2][..], b"hi", &[0, 0, 0, 0], &[0, 0, 0, 3], b"abc"].concat();
+        let messages = BatchFormat::LengthPrefixed.parse(&HeaderMap::new(), &body).unwrap();
+        let data: Vec<&[u8]> = messages.iter().map(|message| message.data.as_slice()).collect();
+        assert_eq!(data, vec![&b"hi"[..], &b""[..], &b"abc"[..]]);
+
+        assert!(BatchFormat::LengthPrefixed.parse(&HeaderMap::new(), &[0, 0]).is_err());
+        assert!(BatchFormat::LengthPrefixed.parse(&HeaderMap::new(), &[0, 0, 0, 3, b'a']).is_err());
+    }
+
+    #[test]
+    fn lets_json_entries_override_shared_headers() {
+        let headers = headers(&[("x-webmq-region", "eu"), ("x-webmq-tier", "gold"), ("x-webmq-ttl", "30s")]);
+        let body = br#"[{"data": "aGk=", "content_type": "text/plain", "headers": {"tier": "silver"}}, {"data": ""}]"#;
+        let messages = BatchFormat::Json.parse(&headers, body).unwrap();
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].data, b"hi");
+        assert_eq!(messages[0].content_type.as_deref(), Some("text/plain"));
+        assert_eq!(messages[0].headers["region"], "eu");
+        assert_eq!(messages[0].headers["tier"], "silver");
+        assert_eq!(messages[1].headers["tier"], "gold");
+        assert!(messages.iter().all(|message| message.expires_at.is_some()));
+    }
+
+    #[test]
+    fn refuses_invalid_json_batches() {
+        let none = HeaderMap::new();
+        assert!(BatchFormat::Json.parse(&none, b"{}").is_err());
+        assert!(BatchFormat::Json.parse(&none, br#"[{"data": "not base64!"}]"#).is_err());
+        assert!(BatchFormat::Json.parse(&none, br#"[{"data": "", "headers": {"ttl": "1s"}}]"#).is_err());
+        assert!(BatchFormat::Json.parse(&headers(&[("x-webmq-ttl", "soon")]), b"[]").is_err());
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+use crate::core::models::message::{DeliveryFailure, Message};
+
+/// Longest part of a message body shown when browsing a queue.
+const PREVIEW_SIZE: usize = 256;
+
+/// A message as shown when browsing a queue, with only the start of its body.
+#[derive(Serialize)]
+pub struct MessagePreview<'a> {
+    id: &'a str,
+    timestamp: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    content_type: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    headers: &'a BTreeMap<String, String>,
+    delivery_count: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dead_letter_source: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    failures: &'a [DeliveryFailure],
+    size: usize,
+    /// The start of the body as UTF-8, with invalid sequences replaced.
+    preview: String,
+    truncated: bool,
+}
+
+impl<'a> From<&'a Message> for MessagePreview<'a> {
+    fn from(message: &'a Message) -> Self {
+        let preview = &message.data[..message.data.len().min(PREVIEW_SIZE)];
+        MessagePreview {
+            id: &message.id,
+            timestamp: message.timestamp,
+            content_type: message.content_type.as_deref(),
+            headers: &message.headers,
+            delivery_count: message.delivery_count,
+            dead_letter_source: message.dead_letter_source.as_deref(),
+            expires_at: message.expires_at,
+            failures: &message.failures,
+            size: message.data.len(),
+            preview: String::from_utf8_lossy(preview).into_owned(),
+            truncated: preview.len() < message.data.len(),
+        }
+    }
+}
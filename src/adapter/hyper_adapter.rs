@@ -1,16 +1,48 @@
+use std::{convert::Infallible, sync::Arc, time::Duration};
+
 use async_trait::async_trait;
-use http_body_util::{BodyExt, Full, };
-use hyper::{body::{Bytes, Incoming}, Method, Request, Response};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited, combinators::BoxBody};
+use hyper::{body::{Bytes, Incoming}, header, Method, Request, Response, StatusCode, Uri};
 use log::{info, warn};
-use tokio::sync::Mutex;
+use serde::Serialize;
+use tokio::time::{timeout_at, Instant};
+
+use crate::{auth::{authenticator::{Authenticator, Principal}, policy::Policy}, core::{config::{auth::QueueOperation, queue::QueueSettings}, errors::WebMQError, models::{identity::ClientIdentity, message::Message}, traits::Adapter}, core::traits::MessagingDispatcher, utils::duration::parse_duration};
+
+use super::{
+    batch::BatchFormat,
+    browse::MessagePreview,
+    limits::MessageLimits,
+    metadata::{MESSAGE_ID_HEADER, RECEIPT_HEADER, SUBSCRIPTIONS_HEADER, message_headers, new_message},
+    multipart::multipart_response,
+    sse::{StreamRegistry, stream_queue},
+    websocket::{is_upgrade_request, upgrade_connection},
+};
 
-use crate::{core::{errors::WebMQError, traits::Adapter}, core::traits::MessagingDispatcher};
+pub type SharedDispatcher = Arc<dyn MessagingDispatcher<String, Message> + Send + Sync>;
 
 pub struct HyperAdapter {
-    pub dispatcher: Mutex<Box<dyn MessagingDispatcher<String, Vec<u8>> + Send + Sync>>
+    pub dispatcher: SharedDispatcher,
+    pub authenticator: Arc<Authenticator>,
+    pub policy: Arc<Policy>,
+    pub limits: Arc<MessageLimits>,
+    pub streams: Arc<StreamRegistry>,
+    pub max_wait: Duration,
+    pub visibility_timeout: Duration,
 }
 
-type Res = Response<Full<Bytes>>;
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
+
+/// Most messages a single consume request may ask for.
+const MAX_CONSUME_COUNT: usize = 1000;
+const DEFAULT_BROWSE_LIMIT: usize = 20;
+const MAX_BROWSE_LIMIT: usize = 100;
+
+/// Nothing wakes a waiting consumer when a lease runs out, so waits are cut
+/// short this often to pick up messages whose visibility timeout expired.
+pub const REDELIVERY_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type Res = Response<BoxBody<Bytes, Infallible>>;
 
 #[async_trait]
 impl Adapter for HyperAdapter {
@@ -18,42 +50,392 @@ impl Adapter for HyperAdapter {
     type Output = Result<Res, WebMQError>;
 
     async fn call(&self, request: Self::Input) -> Self::Output {
-        
-        match request.method() {
-            &Method::GET if request.uri().path().starts_with("/queue") => {
-                let Some(queue) = request.uri().path().strip_prefix("/").unwrap().split("/").nth(1) else {
-                    return Ok(response_404());
+        let path = request.uri().path().to_owned();
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let principal = match self
+            .authenticator
+            .authenticate(request.headers(), request.extensions().get::<ClientIdentity>())
+        {
+            Ok(principal) => principal,
+            Err(e) => {
+                warn!("Refused {} {path}: {e}", request.method());
+                return Ok(response_401());
+            }
+        };
+        if let Some(principal) = &principal {
+            info!("{} {path} by {principal}", request.method());
+        }
+
+        if let Some(queue) = queue_segment(&segments)
+            && !is_valid_queue_name(queue)
+        {
+            warn!("Refused {} {path}: invalid queue name", request.method());
+            return Ok(response_400());
+        }
+
+        if let Some((operation, queue)) = required_permission(request.method(), &segments)
+            && !self.policy.allows(principal.as_ref(), operation, queue)
+        {
+            warn!("Denied {} {path} to {}", request.method(), describe(principal.as_ref()));
+            return Ok(response_403());
+        }
+
+        match (request.method(), segments.as_slice()) {
+            (&Method::GET, ["ws"]) if is_upgrade_request(&request) => {
+                Ok(upgrade_connection(
+                    request,
+                    self.dispatcher.clone(),
+                    self.visibility_timeout,
+                    self.policy.clone(),
+                    principal,
+                    self.limits.clone(),
+                ))
+            }
+            (&Method::GET, ["queue", queue]) => {
+                let wait = match query_param(request.uri(), "wait").map(parse_duration) {
+                    None => None,
+                    Some(Some(wait)) => Some(wait.min(self.max_wait)),
+                    Some(None) => return Ok(response_400()),
+                };
+
+                let Some(visibility_timeout) = self.visibility_timeout(request.uri()) else {
+                    return Ok(response_400());
+                };
+
+                let Ok(batch) = self.consume_batch(request.uri()) else {
+                    return Ok(response_400());
+                };
+
+                Ok(self.consume(queue.to_string(), wait, visibility_timeout, batch).await)
+            }
+            (&Method::GET, ["queue", queue, "stream"]) => {
+                let Some(visibility_timeout) = self.visibility_timeout(request.uri()) else {
+                    return Ok(response_400());
+                };
+
+                let last_event_id = request
+                    .headers()
+                    .get(LAST_EVENT_ID_HEADER)
+                    .and_then(|id| id.to_str().ok())
+                    .map(str::to_string);
+
+                Ok(stream_queue(
+                    self.dispatcher.clone(),
+                    self.streams.clone(),
+                    queue.to_string(),
+                    visibility_timeout,
+                    last_event_id,
+                )
+                .await)
+            }
+            (&Method::GET, ["queue", queue, "messages"]) => {
+                let offset = match query_param(request.uri(), "offset").map(str::parse::<usize>) {
+                    None => 0,
+                    Some(Ok(offset)) => offset,
+                    Some(Err(_)) => return Ok(response_400()),
+                };
+                let limit = match query_param(request.uri(), "limit").map(str::parse::<usize>) {
+                    None => DEFAULT_BROWSE_LIMIT,
+                    Some(Ok(limit)) => limit.min(MAX_BROWSE_LIMIT),
+                    Some(Err(_)) => return Ok(response_400()),
                 };
 
-                let q = queue.to_owned();
-                let res = self.dispatcher.lock().await.consume(q.clone()).await;
+                match self.dispatcher.browse(queue.to_string(), offset, limit).await {
+                    Ok(messages) => {
+                        let previews: Vec<MessagePreview> = messages.iter().map(MessagePreview::from).collect();
+                        Ok(json_response(&previews))
+                    }
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::POST, ["queue", queue, "ack", receipt]) => {
+                let res = self.dispatcher.ack(queue.to_string(), receipt.to_string()).await;
                 match res {
-                    Ok(res) => {
-                        info!("Consumed message on queue {q}");
-                        Ok(Response::builder().body(Full::new(Bytes::from(res))).unwrap())
-                    },
-                    Err(res) => {
-                        warn!("{res}");
+                    None => {
+                        info!("Acknowledged delivery {receipt} on queue {queue}");
+                        Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                    }
+                    Some(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::POST, ["queue", queue, "nack", receipt]) => {
+                let res = self.dispatcher.nack(queue.to_string(), receipt.to_string()).await;
+                match res {
+                    None => {
+                        info!("Released delivery {receipt} on queue {queue}");
                         Ok(Response::builder().status(204).body(empty_body()).unwrap())
-                    } 
-                        
+                    }
+                    Some(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
                 }
+            }
+            (&Method::POST, ["queue", queue, "reject", receipt]) => {
+                let (queue, receipt) = (queue.to_string(), receipt.to_string());
+                let reason = match read_body(request.into_body(), self.limits.default_limit()).await {
+                    Ok(body) => Some(String::from_utf8_lossy(&body).trim().to_string())
+                        .filter(|reason| !reason.is_empty()),
+                    Err(response) => return Ok(response),
+                };
 
+                let res = self.dispatcher.reject(queue.clone(), receipt.clone(), reason).await;
+                match res {
+                    None => {
+                        info!("Rejected delivery {receipt} on queue {queue}");
+                        Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                    }
+                    Some(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
             }
-            &Method::POST if request.uri().path().starts_with("/queue") => {
-                let Some(queue) = request.uri().path().strip_prefix("/").unwrap().split("/").nth(1) else {
-                    return Ok(response_404());
+            (&Method::POST, ["queue", queue, "redrive"]) => {
+                let res = self.dispatcher.redrive(queue.to_string()).await;
+                match res {
+                    Ok(count) => {
+                        info!("Redrove {count} message(s) from queue {queue}");
+                        Ok(Response::builder().body(full_body(count.to_string())).unwrap())
+                    }
+                    Err(e @ WebMQError::Config(_)) => {
+                        warn!("{e}");
+                        Ok(response_400())
+                    }
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::POST, ["queue", queue]) => {
+                let q = queue.to_string();
+                let (parts, body) = request.into_parts();
+                let b = match read_body(body, self.limits.for_queue(&q)).await {
+                    Ok(body) => body,
+                    Err(response) => return Ok(response),
+                };
+
+                let message = match new_message(&parts.headers, b.to_vec()) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Refused message: {e}");
+                        return Ok(response_400());
+                    }
                 };
-                
-                let q = queue.to_owned();
-                let b = request.collect().await.unwrap().to_bytes();
-                self.dispatcher.lock().await.publish(q.clone(), b.to_vec()).await;
-                info!("Posted message on queue {q}");
-                return Ok(Response::builder()
+                let id = message.id.clone();
+                if let Some(e) = self.dispatcher.publish(q.clone(), message).await {
+                    warn!("{e}");
+                    return Ok(Response::builder().status(500).body(empty_body()).unwrap());
+                }
+                info!("Posted message {id} on queue {q}");
+                Ok(Response::builder()
                     .status(202)
+                    .header(MESSAGE_ID_HEADER, id)
                     .body(empty_body())
                     .unwrap())
             },
+            (&Method::POST, ["queue", queue, "batch"]) => {
+                let q = queue.to_string();
+                let (parts, body) = request.into_parts();
+                let Some(format) = BatchFormat::from_headers(&parts.headers) else {
+                    return Ok(response_415());
+                };
+                let b = match read_body(body, self.limits.batch_limit()).await {
+                    Ok(body) => body,
+                    Err(response) => return Ok(response),
+                };
+
+                let messages = match format.parse(&parts.headers, &b) {
+                    Ok(messages) if messages.is_empty() => return Ok(response_400()),
+                    Ok(messages) => messages,
+                    Err(e) => {
+                        warn!("Refused batch for queue {q}: {e}");
+                        return Ok(response_400());
+                    }
+                };
+                let limit = self.limits.for_queue(&q);
+                if messages.iter().any(|message| message.data.len() > limit) {
+                    warn!("Refused batch with a message larger than {limit} bytes");
+                    return Ok(response_413());
+                }
+
+                let ids: Vec<String> = messages.iter().map(|message| message.id.clone()).collect();
+                if let Some(e) = self.dispatcher.publish_batch(q.clone(), messages).await {
+                    warn!("{e}");
+                    return Ok(Response::builder().status(500).body(empty_body()).unwrap());
+                }
+                info!("Posted {} message(s) on queue {q}", ids.len());
+                let mut response = json_response(&ids);
+                *response.status_mut() = StatusCode::ACCEPTED;
+                Ok(response)
+            }
+            (&Method::POST, ["topic", topic]) => {
+                let t = topic.to_string();
+                let subscriptions = self.dispatcher.subscriptions(t.clone()).await;
+                if !self.policy.allows_all(principal.as_ref(), QueueOperation::Publish, &subscriptions) {
+                    warn!("Denied {} {path} to {}", request.method(), describe(principal.as_ref()));
+                    return Ok(response_403());
+                }
+
+                let (parts, body) = request.into_parts();
+                let b = match read_body(body, self.limits.for_queues(&subscriptions)).await {
+                    Ok(body) => body,
+                    Err(response) => return Ok(response),
+                };
+
+                let message = match new_message(&parts.headers, b.to_vec()) {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Refused message: {e}");
+                        return Ok(response_400());
+                    }
+                };
+                let id = message.id.clone();
+                let res = self.dispatcher.publish_topic(t.clone(), message).await;
+                match res {
+                    Ok(count) => {
+                        info!("Posted message {id} on topic {t} to {count} subscription(s)");
+                        Ok(Response::builder()
+                            .status(202)
+                            .header(MESSAGE_ID_HEADER, id)
+                            .header(SUBSCRIPTIONS_HEADER, count)
+                            .body(empty_body())
+                            .unwrap())
+                    }
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(Response::builder().status(500).body(empty_body()).unwrap())
+                    }
+                }
+            }
+            (&Method::GET, ["topic", topic, "subscriptions"]) => {
+                let subscriptions: Vec<String> = self
+                    .dispatcher
+                    .subscriptions(topic.to_string())
+                    .await
+                    .into_iter()
+                    .filter(|queue| self.policy.allows(principal.as_ref(), QueueOperation::Admin, queue))
+                    .collect();
+                Ok(Response::builder()
+                    .body(full_body(subscriptions.join("\n")))
+                    .unwrap())
+            }
+            (&Method::PUT, ["topic", topic, "subscriptions", queue]) => {
+                let res = self.dispatcher.subscribe(topic.to_string(), queue.to_string()).await;
+                match res {
+                    None => {
+                        info!("Subscribed queue {queue} to topic {topic}");
+                        Ok(Response::builder().status(201).body(empty_body()).unwrap())
+                    }
+                    Some(e) => {
+                        info!("{e}");
+                        Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                    }
+                }
+            }
+            (&Method::DELETE, ["topic", topic, "subscriptions", queue]) => {
+                let res = self.dispatcher.unsubscribe(topic.to_string(), queue.to_string()).await;
+                match res {
+                    None => {
+                        info!("Unsubscribed queue {queue} from topic {topic}");
+                        Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                    }
+                    Some(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::GET, ["queues"]) => {
+                let queues: Vec<String> = self
+                    .dispatcher
+                    .queues()
+                    .await
+                    .into_iter()
+                    .filter(|queue| self.policy.allows(principal.as_ref(), QueueOperation::Admin, queue))
+                    .collect();
+                Ok(json_response(&queues))
+            }
+            (&Method::GET, ["queues", queue]) => {
+                match self.dispatcher.queue_info(queue.to_string()).await {
+                    Ok(info) => Ok(json_response(&info)),
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::PUT, ["queues", queue]) => {
+                let q = queue.to_string();
+                let b = match read_body(request.into_body(), self.limits.default_limit()).await {
+                    Ok(body) => body,
+                    Err(response) => return Ok(response),
+                };
+                let settings: QueueSettings = match b.trim_ascii().is_empty() {
+                    true => QueueSettings::default(),
+                    false => match serde_json::from_slice(&b) {
+                        Ok(settings) => settings,
+                        Err(e) => {
+                            warn!("Refused settings for queue {q}: {e}");
+                            return Ok(response_400());
+                        }
+                    },
+                };
+                if let Err(e) = settings.validate(&q) {
+                    warn!("Refused settings for queue {q}: {e}");
+                    return Ok(response_400());
+                }
+
+                let max_message_size = settings.max_message_size;
+                match self.dispatcher.create_queue(q.clone(), settings).await {
+                    Ok(created) => {
+                        self.limits.set_queue(&q, max_message_size);
+                        if created {
+                            info!("Created queue {q}");
+                            Ok(Response::builder().status(201).body(empty_body()).unwrap())
+                        } else {
+                            info!("Updated settings of queue {q}");
+                            Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                        }
+                    }
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(Response::builder().status(500).body(empty_body()).unwrap())
+                    }
+                }
+            }
+            (&Method::DELETE, ["queues", queue]) => {
+                match self.dispatcher.delete_queue(queue.to_string()).await {
+                    None => {
+                        self.limits.reset_queue(queue);
+                        info!("Deleted queue {queue}");
+                        Ok(Response::builder().status(204).body(empty_body()).unwrap())
+                    }
+                    Some(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
+            (&Method::POST, ["queues", queue, "purge"]) => {
+                match self.dispatcher.purge_queue(queue.to_string()).await {
+                    Ok(count) => {
+                        info!("Purged {count} message(s) from queue {queue}");
+                        Ok(Response::builder().body(full_body(count.to_string())).unwrap())
+                    }
+                    Err(e) => {
+                        warn!("{e}");
+                        Ok(response_404())
+                    }
+                }
+            }
             _ => {
                 Ok(response_404())
             }
@@ -61,10 +443,184 @@ impl Adapter for HyperAdapter {
     }
 }
 
-fn empty_body() -> Full<Bytes> {
-    Full::from("")
+impl HyperAdapter {
+    /// Reads the `visibility` query parameter, falling back to the configured
+    /// visibility timeout. Returns `None` if the parameter is malformed.
+    fn visibility_timeout(&self, uri: &Uri) -> Option<Duration> {
+        match query_param(uri, "visibility") {
+            None => Some(self.visibility_timeout),
+            Some(visibility_timeout) => parse_duration(visibility_timeout),
+        }
+    }
+
+    /// Reads the `max` and `max_bytes` query parameters, which ask for up to
+    /// that many messages in a single multipart response. Both are capped, and
+    /// `Ok(None)` is returned if neither is given.
+    fn consume_batch(&self, uri: &Uri) -> Result<Option<(usize, usize)>, ()> {
+        let parse = |name| match query_param(uri, name).map(str::parse::<usize>) {
+            None => Ok(None),
+            Some(Ok(value)) if value > 0 => Ok(Some(value)),
+            Some(_) => Err(()),
+        };
+
+        match (parse("max")?, parse("max_bytes")?) {
+            (None, None) => Ok(None),
+            (max, max_bytes) => Ok(Some((
+                max.unwrap_or(MAX_CONSUME_COUNT).min(MAX_CONSUME_COUNT),
+                max_bytes.unwrap_or(usize::MAX).min(self.limits.batch_limit()),
+            ))),
+        }
+    }
+
+    /// Consumes a message from `queue`, or a `batch` of up to that many
+    /// messages and bytes, parking the request for up to `wait` until
+    /// something is published if the queue is empty.
+    async fn consume(
+        &self,
+        queue: String,
+        wait: Option<Duration>,
+        visibility_timeout: Duration,
+        batch: Option<(usize, usize)>,
+    ) -> Res {
+        let (max, max_bytes) = batch.unwrap_or((1, usize::MAX));
+        let deadline = wait.map(|wait| Instant::now() + wait);
+        loop {
+            // Watching before consuming leaves a permit behind for anything
+            // published in between, so the notification can't be missed.
+            let watcher = self.dispatcher.watch(queue.clone()).await;
+            match self.dispatcher.consume_many(queue.clone(), max, max_bytes, visibility_timeout).await {
+                Ok(deliveries) if batch.is_some() => {
+                    info!("Consumed {} message(s) on queue {queue}", deliveries.len());
+                    return multipart_response(deliveries);
+                }
+                Ok(mut deliveries) => {
+                    let res = deliveries.remove(0);
+                    info!("Consumed message {} on queue {queue}", res.receipt);
+                    let headers = message_headers(&res.data);
+                    let mut response = Response::builder()
+                        .header(RECEIPT_HEADER, res.receipt)
+                        .body(full_body(res.data.data))
+                        .unwrap();
+                    response.headers_mut().extend(headers);
+
+                    return response;
+                }
+                Err(res) if deadline.is_none_or(|deadline| Instant::now() >= deadline) => {
+                    warn!("{res}");
+                    return Response::builder().status(204).body(empty_body()).unwrap();
+                }
+                Err(_) => {}
+            }
+
+            if let Some(deadline) = deadline {
+                let recheck = Instant::now() + REDELIVERY_CHECK_INTERVAL;
+                let _ = timeout_at(deadline.min(recheck), watcher.notified()).await;
+            }
+        }
+    }
+}
+
+/// Returns the queue named in a path, whatever the method.
+fn queue_segment<'a>(segments: &[&'a str]) -> Option<&'a str> {
+    match segments {
+        ["queue" | "queues", queue, ..] | ["topic", _, "subscriptions", queue, ..] => Some(queue),
+        _ => None,
+    }
+}
+
+/// Queue names end up as storage paths, where an empty one would name the
+/// storage root itself.
+pub(super) fn is_valid_queue_name(queue: &str) -> bool {
+    !queue.is_empty() && !queue.chars().any(char::is_control)
+}
+
+/// Returns what a request does to which queue, for requests acting on a
+/// single queue.
+fn required_permission<'a>(method: &Method, segments: &[&'a str]) -> Option<(QueueOperation, &'a str)> {
+    match (method, segments) {
+        (&Method::GET, ["queue", queue])
+        | (&Method::GET, ["queue", queue, "stream"])
+        | (&Method::POST, ["queue", queue, "ack" | "nack" | "reject", _]) => Some((QueueOperation::Consume, queue)),
+        (&Method::POST, ["queue", queue] | ["queue", queue, "batch"]) => Some((QueueOperation::Publish, queue)),
+        (&Method::POST, ["queue", queue, "redrive"])
+        | (&Method::GET, ["queue", queue, "messages"])
+        | (&Method::GET | &Method::PUT | &Method::DELETE, ["queues", queue])
+        | (&Method::POST, ["queues", queue, "purge"])
+        | (&Method::PUT | &Method::DELETE, ["topic", _, "subscriptions", queue]) => Some((QueueOperation::Admin, queue)),
+        _ => None,
+    }
+}
+
+fn describe(principal: Option<&Principal>) -> String {
+    match principal {
+        Some(principal) => principal.to_string(),
+        None => "anonymous caller".to_string(),
+    }
+}
+
+/// Reads the whole request body, refusing it once it grows past `limit`
+/// bytes.
+async fn read_body(body: Incoming, limit: usize) -> Result<Bytes, Res> {
+    match Limited::new(body, limit).collect().await {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) if e.is::<LengthLimitError>() => {
+            warn!("Refused message larger than {limit} bytes");
+            Err(response_413())
+        }
+        Err(e) => {
+            warn!("Could not read request body: {e}");
+            Err(response_400())
+        }
+    }
+}
+
+fn query_param<'a>(uri: &'a Uri, name: &str) -> Option<&'a str> {
+    uri.query()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value)
+}
+
+pub(super) fn full_body(data: impl Into<Bytes>) -> BoxBody<Bytes, Infallible> {
+    Full::new(data.into()).boxed()
+}
+
+fn json_response(value: &impl Serialize) -> Res {
+    Response::builder()
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full_body(serde_json::to_vec(value).unwrap()))
+        .unwrap()
+}
+
+pub(super) fn empty_body() -> BoxBody<Bytes, Infallible> {
+    full_body("")
+}
+
+fn response_400() -> Res {
+    Response::builder().status(400).body(empty_body()).unwrap()
+}
+
+fn response_401() -> Res {
+    Response::builder()
+        .status(401)
+        .header(header::WWW_AUTHENTICATE, "Bearer")
+        .body(empty_body())
+        .unwrap()
+}
+
+fn response_403() -> Res {
+    Response::builder().status(403).body(empty_body()).unwrap()
 }
 
 fn response_404() -> Res {
     Response::builder().status(404).body(empty_body()).unwrap()
+}
+
+fn response_413() -> Res {
+    Response::builder().status(413).body(empty_body()).unwrap()
+}
+
+fn response_415() -> Res {
+    Response::builder().status(415).body(empty_body()).unwrap()
 }
\ No newline at end of file
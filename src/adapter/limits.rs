@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+
+use dashmap::DashMap;
+
+use crate::core::config::queue::QueueSettings;
+
+/// The largest message each queue accepts.
+pub struct MessageLimits {
+    default: usize,
+    batch: usize,
+    queues: DashMap<String, usize>,
+    /// Limits from the configuration, which outlive the queues they are for.
+    configured: HashMap<String, usize>,
+}
+
+impl MessageLimits {
+    pub fn new(default: usize, batch: usize, queue_settings: &HashMap<String, QueueSettings>) -> Self {
+        let configured: HashMap<String, usize> = queue_settings
+            .iter()
+            .filter_map(|(queue, settings)| settings.max_message_size.map(|size| (queue.clone(), size)))
+            .collect();
+        let queues = configured.clone().into_iter().collect();
+
+        Self {
+            default,
+            batch,
+            queues,
+            configured,
+        }
+    }
+
+    pub fn for_queue(&self, queue: &str) -> usize {
+        self.queues.get(queue).map(|limit| *limit).unwrap_or(self.default)
+    }
+
+    /// Replaces the limit of a queue whose settings changed, with `None`
+    /// going back to the default.
+    pub fn set_queue(&self, queue: &str, limit: Option<usize>) {
+        match limit {
+            Some(limit) => {
+                self.queues.insert(queue.to_string(), limit);
+            }
+            None => {
+                self.queues.remove(queue);
+            }
+        }
+    }
+
+    /// Goes back to the configured limit of a deleted queue, dropping any set
+    /// since.
+    pub fn reset_queue(&self, queue: &str) {
+        self.set_queue(queue, self.configured.get(queue).copied());
+    }
+
+    /// The limit for a message going to all of `queues`.
+    pub fn for_queues(&self, queues: &[String]) -> usize {
+        queues
+            .iter()
+            .map(|queue| self.for_queue(queue))
+            .min()
+            .unwrap_or(self.default)
+    }
+
+    pub fn default_limit(&self) -> usize {
+        self.default
+    }
+
+    /// The limit for a whole batch of messages.
+    pub fn batch_limit(&self) -> usize {
+        self.batch
+    }
+}
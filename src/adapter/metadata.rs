@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use hyper::{
+    HeaderMap,
+    header::{self, HeaderName, HeaderValue},
+};
+
+use crate::{core::models::message::Message, utils::duration::parse_duration};
+
+// Lowercase, as `HeaderName::from_static` wants them.
+pub const RECEIPT_HEADER: &str = "x-webmq-receipt";
+pub const MESSAGE_ID_HEADER: &str = "x-webmq-message-id";
+pub const TIMESTAMP_HEADER: &str = "x-webmq-timestamp";
+pub const DELIVERY_COUNT_HEADER: &str = "x-webmq-delivery-count";
+pub const DEAD_LETTER_SOURCE_HEADER: &str = "x-webmq-dead-letter-source";
+pub const FAILURE_HEADER: &str = "x-webmq-failure";
+pub const SUBSCRIPTIONS_HEADER: &str = "x-webmq-subscriptions";
+pub const TTL_HEADER: &str = "x-webmq-ttl";
+pub const EXPIRES_HEADER: &str = "x-webmq-expires";
+
+const USER_HEADER_PREFIX: &str = "x-webmq-";
+
+/// Headers set by the broker, which publishers can't set themselves.
+const RESERVED_HEADERS: [&str; 9] = [
+    RECEIPT_HEADER,
+    MESSAGE_ID_HEADER,
+    TIMESTAMP_HEADER,
+    DELIVERY_COUNT_HEADER,
+    DEAD_LETTER_SOURCE_HEADER,
+    FAILURE_HEADER,
+    SUBSCRIPTIONS_HEADER,
+    TTL_HEADER,
+    EXPIRES_HEADER,
+];
+
+/// Creates a message from a published body, taking its content type, TTL and
+/// `X-WebMQ-*` headers from the request.
+pub fn new_message(headers: &HeaderMap, data: Vec<u8>) -> Result<Message, String> {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+
+    let mut message = Message {
+        content_type,
+        headers: user_headers(headers),
+        ..Message::new(data)
+    };
+    if let Some(ttl) = requested_ttl(headers)? {
+        message.expire_after(ttl);
+    }
+
+    Ok(message)
+}
+
+/// Reads the TTL set by the publisher, such as `30s` or `5m`.
+pub fn requested_ttl(headers: &HeaderMap) -> Result<Option<Duration>, String> {
+    let Some(ttl) = headers.get(TTL_HEADER) else {
+        return Ok(None);
+    };
+
+    match ttl.to_str().ok().and_then(parse_duration) {
+        Some(ttl) => Ok(Some(ttl)),
+        None => Err(format!("Invalid TTL {}", String::from_utf8_lossy(ttl.as_bytes()))),
+    }
+}
+
+/// Collects the `X-WebMQ-*` headers set by the publisher, without their
+/// prefix.
+pub fn user_headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut user_headers = BTreeMap::new();
+    for (name, value) in headers {
+        let Some(name) = name.as_str().strip_prefix(USER_HEADER_PREFIX) else {
+            continue;
+        };
+        let Ok(value) = value.to_str() else {
+            continue;
+        };
+        if user_header_name(name).is_some() {
+            user_headers.insert(name.to_string(), value.to_string());
+        }
+    }
+
+    user_headers
+}
+
+/// Returns the response header a user defined header is sent back as, unless
+/// it would clash with one set by the broker.
+pub fn user_header_name(name: &str) -> Option<HeaderName> {
+    let name = format!("{USER_HEADER_PREFIX}{name}");
+    if name.len() == USER_HEADER_PREFIX.len()
+        || RESERVED_HEADERS.iter().any(|reserved| reserved.eq_ignore_ascii_case(&name))
+    {
+        return None;
+    }
+
+    HeaderName::try_from(name).ok()
+}
+
+/// Describes `message` in headers, for a response or a part of one.
+pub fn message_headers(message: &Message) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let mut append = |name: HeaderName, value: String| {
+        if let Ok(value) = HeaderValue::try_from(header_safe(&value)) {
+            headers.append(name, value);
+        }
+    };
+
+    append(HeaderName::from_static(MESSAGE_ID_HEADER), message.id.clone());
+    append(HeaderName::from_static(TIMESTAMP_HEADER), message.timestamp.to_string());
+    append(HeaderName::from_static(DELIVERY_COUNT_HEADER), message.delivery_count.to_string());
+    if let Some(content_type) = &message.content_type {
+        append(header::CONTENT_TYPE, content_type.clone());
+    }
+    if let Some(expires_at) = message.expires_at {
+        append(HeaderName::from_static(EXPIRES_HEADER), expires_at.to_string());
+    }
+    if let Some(source) = &message.dead_letter_source {
+        append(HeaderName::from_static(DEAD_LETTER_SOURCE_HEADER), source.clone());
+    }
+    for failure in &message.failures {
+        append(
+            HeaderName::from_static(FAILURE_HEADER),
+            format!("{}; {}", failure.timestamp, failure.reason),
+        );
+    }
+    for (name, value) in &message.headers {
+        if let Some(name) = user_header_name(name) {
+            append(name, value.clone());
+        }
+    }
+
+    headers
+}
+
+/// Replaces anything that isn't allowed in a header value.
+pub fn header_safe(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| if c == ' ' || c.is_ascii_graphic() { c } else { '?' })
+        .collect()
+}
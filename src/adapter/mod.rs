@@ -1 +1,8 @@
-pub mod hyper_adapter;
\ No newline at end of file
+pub mod batch;
+pub mod browse;
+pub mod hyper_adapter;
+pub mod limits;
+pub mod metadata;
+pub mod multipart;
+pub mod sse;
+pub mod websocket;
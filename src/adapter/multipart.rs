@@ -0,0 +1,42 @@
+use hyper::{
+    Response,
+    header::{self, HeaderValue},
+};
+use uuid::Uuid;
+
+use crate::core::models::{delivery::Delivery, message::Message};
+
+use super::{
+    hyper_adapter::{Res, full_body},
+    metadata::{RECEIPT_HEADER, message_headers},
+};
+
+/// Sends a batch of deliveries as a `multipart/mixed` response with one part
+/// per message, carrying the same headers a single message is sent with.
+pub fn multipart_response(deliveries: Vec<Delivery<Message>>) -> Res {
+    let boundary = Uuid::new_v4().simple().to_string();
+    let mut body = vec![];
+    for delivery in deliveries {
+        let mut headers = message_headers(&delivery.data);
+        if let Ok(receipt) = HeaderValue::try_from(delivery.receipt) {
+            headers.insert(RECEIPT_HEADER, receipt);
+        }
+
+        body.extend_from_slice(format!("--{boundary}\r\n").as_bytes());
+        for (name, value) in &headers {
+            body.extend_from_slice(name.as_str().as_bytes());
+            body.extend_from_slice(b": ");
+            body.extend_from_slice(value.as_bytes());
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(b"\r\n");
+        body.extend_from_slice(&delivery.data.data);
+        body.extend_from_slice(b"\r\n");
+    }
+    body.extend_from_slice(format!("--{boundary}--\r\n").as_bytes());
+
+    Response::builder()
+        .header(header::CONTENT_TYPE, format!("multipart/mixed; boundary={boundary}"))
+        .body(full_body(body))
+        .unwrap()
+}
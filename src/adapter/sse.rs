@@ -0,0 +1,207 @@
+use std::{collections::{HashMap, VecDeque}, convert::Infallible, sync::Arc, time::Duration};
+
+use http_body_util::{BodyExt, StreamBody};
+use hyper::{Response, body::{Bytes, Frame}};
+use log::{debug, info, warn};
+use tokio::{sync::{Mutex, mpsc}, time::{Instant, timeout}};
+use tokio_stream::wrappers::ReceiverStream;
+use uuid::Uuid;
+
+use crate::core::models::message::Message;
+
+use super::hyper_adapter::{REDELIVERY_CHECK_INTERVAL, Res, SharedDispatcher};
+
+const EVENT_BUFFER_SIZE: usize = 1;
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+type Event = Result<Frame<Bytes>, Infallible>;
+
+/// Receipts sent on a single event stream, in the order they were sent.
+struct StreamLog {
+    queue: String,
+    sent: VecDeque<(String, Instant)>,
+    closed: bool,
+}
+
+#[derive(Default)]
+struct Streams {
+    logs: HashMap<String, StreamLog>,
+    /// The stream every receipt in `logs` was sent on.
+    senders: HashMap<String, String>,
+}
+
+impl Streams {
+    /// Forgets receipts whose leases have run out, as those messages come
+    /// back on their own.
+    fn prune(&mut self, stream: &str, now: Instant) {
+        let Some(log) = self.logs.get_mut(stream) else {
+            return;
+        };
+        while log.sent.front().is_some_and(|(_, expires_at)| *expires_at <= now) {
+            if let Some((receipt, _)) = log.sent.pop_front() {
+                self.senders.remove(&receipt);
+            }
+        }
+    }
+}
+
+/// Keeps track of what every event stream has sent, so a client resuming with
+/// `Last-Event-ID` gets the messages it missed back without having to wait
+/// for their visibility timeout.
+#[derive(Default)]
+pub struct StreamRegistry {
+    streams: Mutex<Streams>,
+}
+
+impl StreamRegistry {
+    async fn open(&self, queue: &str) -> String {
+        let id = Uuid::new_v4().to_string();
+        self.streams.lock().await.logs.insert(id.clone(), StreamLog {
+            queue: queue.to_string(),
+            sent: VecDeque::new(),
+            closed: false,
+        });
+        id
+    }
+
+    async fn record(&self, stream: &str, receipt: String, visibility_timeout: Duration) {
+        let mut streams = self.streams.lock().await;
+        let now = Instant::now();
+        streams.prune(stream, now);
+        if let Some(log) = streams.logs.get_mut(stream) {
+            log.sent.push_back((receipt.clone(), now + visibility_timeout));
+            streams.senders.insert(receipt, stream.to_string());
+        }
+    }
+
+    async fn close(&self, stream: &str) {
+        let mut streams = self.streams.lock().await;
+        if let Some(log) = streams.logs.get_mut(stream) {
+            log.closed = true;
+        }
+
+        let now = Instant::now();
+        let ids: Vec<String> = streams.logs.keys().cloned().collect();
+        for id in ids {
+            streams.prune(&id, now);
+        }
+        streams.logs.retain(|_, log| !(log.closed && log.sent.is_empty()));
+    }
+
+    /// Returns the receipts which were sent on `queue` after `last_event_id`
+    /// by the stream that sent it.
+    async fn missed(&self, queue: &str, last_event_id: &str) -> Vec<String> {
+        let mut streams = self.streams.lock().await;
+        let Some(stream) = streams.senders.get(last_event_id).cloned() else {
+            return vec![];
+        };
+        let Some(log) = streams.logs.get_mut(&stream) else {
+            return vec![];
+        };
+        if log.queue != queue {
+            return vec![];
+        }
+        let Some(index) = log.sent.iter().position(|(receipt, _)| receipt == last_event_id) else {
+            return vec![];
+        };
+
+        let missed: Vec<String> = log.sent.drain(index + 1..).map(|(receipt, _)| receipt).collect();
+        for receipt in &missed {
+            streams.senders.remove(receipt);
+        }
+        missed
+    }
+}
+
+/// Streams messages from `queue` as server-sent events until the client goes
+/// away. Each event carries the delivery receipt as its id.
+pub async fn stream_queue(
+    dispatcher: SharedDispatcher,
+    registry: Arc<StreamRegistry>,
+    queue: String,
+    visibility_timeout: Duration,
+    last_event_id: Option<String>,
+) -> Res {
+    if let Some(last_event_id) = last_event_id {
+        let missed = registry.missed(&queue, &last_event_id).await;
+        for receipt in missed {
+            if dispatcher.return_delivery(queue.clone(), receipt.clone()).await.is_none() {
+                debug!("Released delivery {receipt} missed by resumed stream on queue {queue}");
+            }
+        }
+    }
+
+    let (sender, receiver) = mpsc::channel::<Event>(EVENT_BUFFER_SIZE);
+    let stream_id = registry.open(&queue).await;
+    info!("Opened event stream {stream_id} on queue {queue}");
+
+    tokio::task::spawn(async move {
+        send_events(&dispatcher, &registry, &stream_id, &queue, visibility_timeout, sender).await;
+        registry.close(&stream_id).await;
+        info!("Closed event stream {stream_id} on queue {queue}");
+    });
+
+    Response::builder()
+        .header("Content-Type", "text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .body(StreamBody::new(ReceiverStream::new(receiver)).boxed())
+        .unwrap()
+}
+
+async fn send_events(
+    dispatcher: &SharedDispatcher,
+    registry: &StreamRegistry,
+    stream_id: &str,
+    queue: &str,
+    visibility_timeout: Duration,
+    sender: mpsc::Sender<Event>,
+) {
+    let mut last_sent = Instant::now();
+    loop {
+        let watcher = dispatcher.watch(queue.to_string()).await;
+        let delivery = match dispatcher.consume(queue.to_string(), visibility_timeout).await {
+            Ok(delivery) => Ok(delivery),
+            Err(_) => Err(watcher),
+        };
+
+        let delivery = match delivery {
+            Ok(delivery) => delivery,
+            Err(watcher) => {
+                if timeout(REDELIVERY_CHECK_INTERVAL, watcher.notified()).await.is_err()
+                    && last_sent.elapsed() >= KEEPALIVE_INTERVAL
+                {
+                    if sender.send(Ok(Frame::data(Bytes::from_static(b": keepalive\n\n")))).await.is_err() {
+                        return;
+                    }
+                    last_sent = Instant::now();
+                }
+                continue;
+            }
+        };
+
+        let receipt = delivery.receipt;
+        let event = format_event(&receipt, &delivery.data);
+        registry.record(stream_id, receipt.clone(), visibility_timeout).await;
+        if sender.send(Ok(Frame::data(event))).await.is_err() {
+            // The client is gone, so this one never left the broker.
+            if let Some(e) = dispatcher.return_delivery(queue.to_string(), receipt).await {
+                warn!("{e}");
+            }
+            return;
+        }
+        last_sent = Instant::now();
+    }
+}
+
+fn format_event(receipt: &str, message: &Message) -> Bytes {
+    let mut event = format!("id: {receipt}\nevent: message\n");
+    let data = String::from_utf8_lossy(&message.data);
+    for line in data.split('\n') {
+        event.push_str("data: ");
+        event.push_str(line.strip_suffix('\r').unwrap_or(line));
+        event.push('\n');
+    }
+    event.push('\n');
+
+    Bytes::from(event)
+}
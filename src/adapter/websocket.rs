@@ -0,0 +1,502 @@
+use std::{collections::{BTreeMap, HashMap}, sync::Arc, time::Duration};
+
+use base64::{Engine, prelude::BASE64_STANDARD};
+use futures_util::{SinkExt, StreamExt};
+use hyper::{Request, Response, StatusCode, body::Incoming, header, upgrade};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{sync::{Mutex, Semaphore, mpsc}, task::JoinHandle, time::timeout};
+use tokio_tungstenite::{
+    WebSocketStream,
+    tungstenite::{self, handshake::derive_accept_key, protocol::Role},
+};
+
+use crate::{
+    auth::{authenticator::Principal, policy::Policy},
+    core::{config::auth::QueueOperation, models::message::Message},
+    utils::duration::parse_duration,
+};
+
+use super::{
+    hyper_adapter::{REDELIVERY_CHECK_INTERVAL, Res, SharedDispatcher, empty_body, is_valid_queue_name},
+    limits::MessageLimits,
+    metadata::user_header_name,
+};
+
+const OUTGOING_BUFFER_SIZE: usize = 64;
+const DEFAULT_PREFETCH: u32 = 10;
+
+type WsMessage = tungstenite::Message;
+
+/// A frame sent by the client. `id` is echoed back in the reply so clients can
+/// correlate requests with results.
+#[derive(Deserialize)]
+struct ClientFrame {
+    id: Option<String>,
+    #[serde(flatten)]
+    operation: Operation,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Operation {
+    /// Starts delivering messages from `queue`, keeping at most `prefetch`
+    /// of them unacknowledged at a time.
+    Subscribe {
+        queue: String,
+        prefetch: Option<u32>,
+        visibility: Option<String>,
+    },
+    Unsubscribe {
+        queue: String,
+    },
+    /// Publishes base64 encoded `data` to either a queue or a topic.
+    Publish {
+        queue: Option<String>,
+        topic: Option<String>,
+        data: String,
+        content_type: Option<String>,
+        #[serde(default)]
+        headers: BTreeMap<String, String>,
+        /// How long the message lives for, such as `30s` or `5m`.
+        ttl: Option<String>,
+    },
+    Ack {
+        queue: String,
+        receipt: String,
+    },
+    Nack {
+        queue: String,
+        receipt: String,
+    },
+    /// Allows `credit` more messages to be delivered from `queue` on top of
+    /// those freed up by acknowledgements.
+    Credit {
+        queue: String,
+        credit: u32,
+    },
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ServerFrame<'a> {
+    Ok {
+        id: Option<&'a str>,
+    },
+    Error {
+        id: Option<&'a str>,
+        message: String,
+    },
+    Message {
+        queue: &'a str,
+        receipt: &'a str,
+        message_id: &'a str,
+        timestamp: u64,
+        delivery_count: u32,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        content_type: Option<&'a str>,
+        #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+        headers: &'a BTreeMap<String, String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        expires_at: Option<u64>,
+        data: String,
+    },
+}
+
+struct Subscription {
+    credit: Arc<Semaphore>,
+    task: JoinHandle<()>,
+}
+
+/// A frame on its way to the client, along with the receipt of the delivery
+/// it carries.
+struct Outgoing {
+    frame: WsMessage,
+    receipt: Option<String>,
+}
+
+/// A delivery which hasn't been acknowledged yet.
+struct Outstanding {
+    queue: String,
+    /// Whether the delivery has been written to the socket.
+    written: bool,
+}
+
+/// State of a single WebSocket connection.
+struct Connection {
+    dispatcher: SharedDispatcher,
+    visibility_timeout: Duration,
+    policy: Arc<Policy>,
+    principal: Option<Principal>,
+    limits: Arc<MessageLimits>,
+    outgoing: mpsc::Sender<Outgoing>,
+    subscriptions: HashMap<String, Subscription>,
+    /// Every delivery which hasn't been acknowledged yet, keyed by receipt.
+    outstanding: Arc<Mutex<HashMap<String, Outstanding>>>,
+}
+
+pub fn is_upgrade_request(request: &Request<Incoming>) -> bool {
+    request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"))
+}
+
+/// Answers a WebSocket handshake and hands the upgraded connection off to a
+/// background task speaking the WebMQ protocol.
+pub fn upgrade_connection(
+    mut request: Request<Incoming>,
+    dispatcher: SharedDispatcher,
+    visibility_timeout: Duration,
+    policy: Arc<Policy>,
+    principal: Option<Principal>,
+    limits: Arc<MessageLimits>,
+) -> Res {
+    let Some(key) = request.headers().get(header::SEC_WEBSOCKET_KEY) else {
+        return Response::builder()
+            .status(StatusCode::BAD_REQUEST)
+            .body(empty_body())
+            .unwrap();
+    };
+    let accept = derive_accept_key(key.as_bytes());
+
+    tokio::task::spawn(async move {
+        match upgrade::on(&mut request).await {
+            Ok(upgraded) => {
+                let stream =
+                    WebSocketStream::from_raw_socket(TokioIo::new(upgraded), Role::Server, None).await;
+                serve(stream, dispatcher, visibility_timeout, policy, principal, limits).await;
+            }
+            Err(e) => warn!("Could not upgrade connection to WebSocket: {e}"),
+        }
+    });
+
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header(header::CONNECTION, "Upgrade")
+        .header(header::UPGRADE, "websocket")
+        .header(header::SEC_WEBSOCKET_ACCEPT, accept)
+        .body(empty_body())
+        .unwrap()
+}
+
+async fn serve<S>(
+    stream: WebSocketStream<S>,
+    dispatcher: SharedDispatcher,
+    visibility_timeout: Duration,
+    policy: Arc<Policy>,
+    principal: Option<Principal>,
+    limits: Arc<MessageLimits>,
+) where
+    S: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let (mut sink, mut source) = stream.split();
+    let (outgoing, mut receiver) = mpsc::channel::<Outgoing>(OUTGOING_BUFFER_SIZE);
+    let outstanding: Arc<Mutex<HashMap<String, Outstanding>>> = Arc::default();
+    let written = outstanding.clone();
+    let writer = tokio::task::spawn(async move {
+        while let Some(message) = receiver.recv().await {
+            if let Err(e) = sink.send(message.frame).await {
+                debug!("Could not write to WebSocket: {e}");
+                break;
+            }
+            if let Some(receipt) = message.receipt
+                && let Some(delivery) = written.lock().await.get_mut(&receipt)
+            {
+                delivery.written = true;
+            }
+        }
+    });
+
+    info!("Opened WebSocket connection");
+    let mut connection = Connection {
+        dispatcher,
+        visibility_timeout,
+        policy,
+        principal,
+        limits,
+        outgoing,
+        subscriptions: HashMap::new(),
+        outstanding,
+    };
+
+    while let Some(frame) = source.next().await {
+        match frame {
+            Ok(WsMessage::Text(text)) => connection.handle(text.as_str()).await,
+            Ok(WsMessage::Binary(_)) => {
+                connection
+                    .reply(ServerFrame::Error {
+                        id: None,
+                        message: "Frames have to be JSON text".to_string(),
+                    })
+                    .await
+            }
+            Ok(WsMessage::Close(_)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                debug!("Error reading from WebSocket: {e}");
+                break;
+            }
+        }
+    }
+
+    // Stop writing first, so whatever is still buffered is known to have
+    // never reached the client.
+    writer.abort();
+    let _ = writer.await;
+    connection.close().await;
+    info!("Closed WebSocket connection");
+}
+
+impl Connection {
+    async fn handle(&mut self, text: &str) {
+        let frame: ClientFrame = match serde_json::from_str(text) {
+            Ok(frame) => frame,
+            Err(e) => {
+                self.reply(ServerFrame::Error {
+                    id: None,
+                    message: format!("Malformed frame: {e}"),
+                })
+                .await;
+                return;
+            }
+        };
+
+        let id = frame.id.as_deref();
+        let reply = match self.execute(frame.operation).await {
+            Ok(()) => ServerFrame::Ok { id },
+            Err(message) => ServerFrame::Error { id, message },
+        };
+        self.reply(reply).await;
+    }
+
+    async fn execute(&mut self, operation: Operation) -> Result<(), String> {
+        match operation {
+            Operation::Subscribe {
+                queue,
+                prefetch,
+                visibility,
+            } => {
+                self.authorize(QueueOperation::Consume, &queue)?;
+                let visibility_timeout = match visibility {
+                    Some(visibility) => parse_duration(&visibility)
+                        .ok_or_else(|| format!("Invalid visibility timeout {visibility}"))?,
+                    None => self.visibility_timeout,
+                };
+                self.subscribe(queue, prefetch.unwrap_or(DEFAULT_PREFETCH), visibility_timeout);
+                Ok(())
+            }
+            Operation::Unsubscribe { queue } => match self.subscriptions.remove(&queue) {
+                Some(subscription) => {
+                    subscription.task.abort();
+                    Ok(())
+                }
+                None => Err(format!("Not subscribed to queue {queue}")),
+            },
+            Operation::Publish { queue, topic, data, content_type, headers, ttl } => {
+                let data = BASE64_STANDARD
+                    .decode(data)
+                    .map_err(|e| format!("Invalid message data: {e}"))?;
+                if let Some(name) = headers.keys().find(|name| user_header_name(name).is_none()) {
+                    return Err(format!("Invalid header name {name}"));
+                }
+                let mut message = Message {
+                    content_type,
+                    headers,
+                    ..Message::new(data)
+                };
+                if let Some(ttl) = ttl {
+                    let Some(ttl) = parse_duration(&ttl) else {
+                        return Err(format!("Invalid TTL {ttl}"));
+                    };
+                    message.expire_after(ttl);
+                }
+                let dispatcher = &self.dispatcher;
+                match (queue, topic) {
+                    (Some(queue), None) => {
+                        self.authorize(QueueOperation::Publish, &queue)?;
+                        check_size(&message.data, self.limits.for_queue(&queue))?;
+                        match dispatcher.publish(queue, message).await {
+                            Some(e) => Err(e.to_string()),
+                            None => Ok(()),
+                        }
+                    }
+                    (None, Some(topic)) => {
+                        let subscriptions = dispatcher.subscriptions(topic.clone()).await;
+                        if !self.policy.allows_all(self.principal.as_ref(), QueueOperation::Publish, &subscriptions) {
+                            return Err(format!("Not allowed to publish to topic {topic}"));
+                        }
+                        check_size(&message.data, self.limits.for_queues(&subscriptions))?;
+                        dispatcher
+                            .publish_topic(topic, message)
+                            .await
+                            .map(|_| ())
+                            .map_err(|e| e.to_string())
+                    }
+                    _ => Err("Exactly one of queue or topic has to be given".to_string()),
+                }
+            }
+            Operation::Ack { queue, receipt } => {
+                self.authorize(QueueOperation::Consume, &queue)?;
+                let res = self.dispatcher.ack(queue, receipt.clone()).await;
+                self.settle(&receipt).await;
+                match res {
+                    Some(e) => Err(e.to_string()),
+                    None => Ok(()),
+                }
+            }
+            Operation::Nack { queue, receipt } => {
+                self.authorize(QueueOperation::Consume, &queue)?;
+                let res = self.dispatcher.nack(queue, receipt.clone()).await;
+                self.settle(&receipt).await;
+                match res {
+                    Some(e) => Err(e.to_string()),
+                    None => Ok(()),
+                }
+            }
+            Operation::Credit { queue, credit } => match self.subscriptions.get(&queue) {
+                Some(subscription) => {
+                    subscription.credit.add_permits(credit as usize);
+                    Ok(())
+                }
+                None => Err(format!("Not subscribed to queue {queue}")),
+            },
+        }
+    }
+
+    fn subscribe(&mut self, queue: String, prefetch: u32, visibility_timeout: Duration) {
+        if let Some(previous) = self.subscriptions.remove(&queue) {
+            previous.task.abort();
+        }
+
+        let credit = Arc::new(Semaphore::new(prefetch as usize));
+        let task = tokio::task::spawn(deliver(
+            self.dispatcher.clone(),
+            queue.clone(),
+            visibility_timeout,
+            credit.clone(),
+            self.outstanding.clone(),
+            self.outgoing.clone(),
+        ));
+
+        info!("WebSocket subscribed to queue {queue} with prefetch {prefetch}");
+        self.subscriptions.insert(queue, Subscription { credit, task });
+    }
+
+    fn authorize(&self, operation: QueueOperation, queue: &str) -> Result<(), String> {
+        if !is_valid_queue_name(queue) {
+            return Err(format!("Invalid queue name {queue:?}"));
+        }
+
+        match self.policy.allows(self.principal.as_ref(), operation, queue) {
+            true => Ok(()),
+            false => Err(format!("Not allowed to {operation:?} on queue {queue}")),
+        }
+    }
+
+    /// Returns the credit used by a delivery to its subscription.
+    async fn settle(&self, receipt: &str) {
+        let Some(delivery) = self.outstanding.lock().await.remove(receipt) else {
+            return;
+        };
+
+        if let Some(subscription) = self.subscriptions.get(&delivery.queue) {
+            subscription.credit.add_permits(1);
+        }
+    }
+
+    async fn reply(&self, frame: ServerFrame<'_>) {
+        if let Ok(text) = serde_json::to_string(&frame) {
+            let _ = self
+                .outgoing
+                .send(Outgoing {
+                    frame: WsMessage::text(text),
+                    receipt: None,
+                })
+                .await;
+        }
+    }
+
+    /// Stops every subscription and releases whatever the client didn't
+    /// acknowledge, so it gets redelivered right away. Deliveries which never
+    /// reached the client are returned without counting as a failed delivery.
+    async fn close(&mut self) {
+        for (_, subscription) in self.subscriptions.drain() {
+            subscription.task.abort();
+        }
+
+        let outstanding: Vec<(String, Outstanding)> = self.outstanding.lock().await.drain().collect();
+        for (receipt, delivery) in outstanding {
+            let res = match delivery.written {
+                true => self.dispatcher.nack(delivery.queue, receipt.clone()).await,
+                false => self.dispatcher.return_delivery(delivery.queue, receipt.clone()).await,
+            };
+            if res.is_none() {
+                debug!("Released unacknowledged delivery {receipt}");
+            }
+        }
+    }
+}
+
+fn check_size(data: &[u8], limit: usize) -> Result<(), String> {
+    match data.len() > limit {
+        true => Err(format!("Message exceeds the maximum size of {limit} bytes")),
+        false => Ok(()),
+    }
+}
+
+async fn deliver(
+    dispatcher: SharedDispatcher,
+    queue: String,
+    visibility_timeout: Duration,
+    credit: Arc<Semaphore>,
+    outstanding: Arc<Mutex<HashMap<String, Outstanding>>>,
+    outgoing: mpsc::Sender<Outgoing>,
+) {
+    loop {
+        let Ok(permit) = credit.acquire().await else {
+            return;
+        };
+        permit.forget();
+
+        let delivery = loop {
+            let watcher = dispatcher.watch(queue.clone()).await;
+            match dispatcher.consume(queue.clone(), visibility_timeout).await {
+                Ok(delivery) => break delivery,
+                Err(_) => {
+                    let _ = timeout(REDELIVERY_CHECK_INTERVAL, watcher.notified()).await;
+                }
+            }
+        };
+
+        outstanding.lock().await.insert(delivery.receipt.clone(), Outstanding {
+            queue: queue.clone(),
+            written: false,
+        });
+
+        let frame = ServerFrame::Message {
+            queue: &queue,
+            receipt: &delivery.receipt,
+            message_id: &delivery.data.id,
+            timestamp: delivery.data.timestamp,
+            delivery_count: delivery.data.delivery_count,
+            content_type: delivery.data.content_type.as_deref(),
+            headers: &delivery.data.headers,
+            expires_at: delivery.data.expires_at,
+            data: BASE64_STANDARD.encode(&delivery.data.data),
+        };
+        let Ok(text) = serde_json::to_string(&frame) else {
+            continue;
+        };
+
+        let message = Outgoing {
+            frame: WsMessage::text(text),
+            receipt: Some(delivery.receipt.clone()),
+        };
+        if outgoing.send(message).await.is_err() {
+            return;
+        }
+    }
+}
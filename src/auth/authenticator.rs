@@ -0,0 +1,219 @@
+use std::{fmt::Display, path::Path};
+
+use hyper::{HeaderMap, header};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode};
+use log::info;
+use serde_json::{Map, Value};
+
+use crate::{
+    core::{
+        config::auth::{AuthSettings, JwtAlgorithm, JwtSettings},
+        errors::WebMQError,
+        models::identity::ClientIdentity,
+    },
+    utils::file::get_file_buffer,
+};
+
+const BEARER_PREFIX: &str = "Bearer ";
+
+/// Who a request is made by, and how they proved it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Principal {
+    pub name: String,
+    pub credential: Credential,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Credential {
+    ApiKey,
+    Token,
+    Certificate,
+}
+
+impl Credential {
+    pub const ALL: [Credential; 3] = [Credential::ApiKey, Credential::Token, Credential::Certificate];
+
+    /// Namespace the names proven by this credential live in, so an API key
+    /// can't pass for a certificate subject of the same name.
+    pub fn prefix(self) -> &'static str {
+        match self {
+            Credential::ApiKey => "key:",
+            Credential::Token => "jwt:",
+            Credential::Certificate => "cert:",
+        }
+    }
+}
+
+/// Written as the name prefixed with its credential type, such as
+/// `key:alice` or `cert:CN=client`.
+impl Display for Principal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.credential.prefix(), self.name)
+    }
+}
+
+struct JwtVerifier {
+    key: DecodingKey,
+    validation: Validation,
+    principal_claim: String,
+}
+
+/// Checks the credentials requests carry. Requests may authenticate with an
+/// API key or a JWT as a bearer token, or with a verified client certificate.
+pub struct Authenticator {
+    api_keys: Vec<(Vec<u8>, String)>,
+    jwt: Option<JwtVerifier>,
+}
+
+impl Authenticator {
+    pub fn load(settings: &AuthSettings) -> Result<Self, WebMQError> {
+        let mut api_keys = vec![];
+        for api_key in &settings.api_keys {
+            let key = read_key_file(&api_key.key_file)?;
+            if key.is_empty() {
+                return Err(WebMQError::Config(format!(
+                    "API key file {} is empty",
+                    api_key.key_file
+                )));
+            }
+            api_keys.push((key, api_key.principal.clone()));
+        }
+
+        let jwt = match &settings.jwt {
+            Some(jwt) => Some(JwtVerifier::load(jwt)?),
+            None => None,
+        };
+
+        let authenticator = Self { api_keys, jwt };
+        if authenticator.is_enabled() {
+            info!(
+                "Loaded {} API key(s){}",
+                authenticator.api_keys.len(),
+                if authenticator.jwt.is_some() { " and a JWT key" } else { "" }
+            );
+        }
+
+        Ok(authenticator)
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.api_keys.is_empty() || self.jwt.is_some()
+    }
+
+    /// Returns who made a request. With authentication disabled, anonymous
+    /// requests are let through and bearer tokens aren't looked at.
+    pub fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        identity: Option<&ClientIdentity>,
+    ) -> Result<Option<Principal>, WebMQError> {
+        let certificate = identity.map(|identity| Principal {
+            name: identity.subject.clone(),
+            credential: Credential::Certificate,
+        });
+        if !self.is_enabled() {
+            return Ok(certificate);
+        }
+
+        let Some(authorization) = headers.get(header::AUTHORIZATION) else {
+            return match certificate {
+                Some(principal) => Ok(Some(principal)),
+                None => Err(WebMQError::Auth("Missing credentials".to_string())),
+            };
+        };
+
+        let Some(token) = authorization
+            .to_str()
+            .ok()
+            .and_then(|authorization| authorization.strip_prefix(BEARER_PREFIX))
+        else {
+            return Err(WebMQError::Auth(
+                "Unsupported authorization scheme".to_string(),
+            ));
+        };
+
+        if let Some(principal) = self.find_api_key(token.trim()) {
+            return Ok(Some(Principal {
+                name: principal.to_string(),
+                credential: Credential::ApiKey,
+            }));
+        }
+
+        match &self.jwt {
+            Some(jwt) => jwt.verify(token.trim()).map(|name| {
+                Some(Principal {
+                    name,
+                    credential: Credential::Token,
+                })
+            }),
+            None => Err(WebMQError::Auth("Unknown API key".to_string())),
+        }
+    }
+
+    fn find_api_key(&self, token: &str) -> Option<&str> {
+        self.api_keys
+            .iter()
+            .find(|(key, _)| constant_time_eq(key, token.as_bytes()))
+            .map(|(_, principal)| principal.as_str())
+    }
+}
+
+impl JwtVerifier {
+    fn load(settings: &JwtSettings) -> Result<Self, WebMQError> {
+        let key_file = read_key_file(&settings.key_file)?;
+        let (algorithm, key) = match settings.algorithm {
+            JwtAlgorithm::HS256 => (Algorithm::HS256, Ok(DecodingKey::from_secret(&key_file))),
+            JwtAlgorithm::RS256 => (Algorithm::RS256, DecodingKey::from_rsa_pem(&key_file)),
+        };
+        let key = match key {
+            Ok(key) => key,
+            Err(e) => {
+                return Err(WebMQError::Config(format!(
+                    "Couldn't load JWT key from {}: {}",
+                    settings.key_file, e
+                )));
+            }
+        };
+
+        let mut validation = Validation::new(algorithm);
+        if let Some(issuer) = &settings.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+        match &settings.audience {
+            Some(audience) => validation.set_audience(&[audience]),
+            None => validation.validate_aud = false,
+        }
+
+        Ok(Self {
+            key,
+            validation,
+            principal_claim: settings.principal_claim.clone(),
+        })
+    }
+
+    fn verify(&self, token: &str) -> Result<String, WebMQError> {
+        let claims = match decode::<Map<String, Value>>(token, &self.key, &self.validation) {
+            Ok(data) => data.claims,
+            Err(e) => return Err(WebMQError::Auth(format!("Invalid token: {}", e))),
+        };
+
+        match claims.get(&self.principal_claim) {
+            Some(Value::String(principal)) => Ok(principal.clone()),
+            _ => Err(WebMQError::Auth(format!(
+                "Token has no {} claim",
+                self.principal_claim
+            ))),
+        }
+    }
+}
+
+fn read_key_file(path: &str) -> Result<Vec<u8>, WebMQError> {
+    let mut key = get_file_buffer(Path::new(path))?;
+    let length = key.trim_ascii_end().len();
+    key.truncate(length);
+    Ok(key)
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
@@ -0,0 +1,132 @@
+use log::info;
+
+use crate::{
+    core::{
+        config::auth::{PolicySettings, QueueOperation},
+        errors::WebMQError,
+    },
+    utils::pattern::matches_pattern,
+};
+
+use super::authenticator::{Credential, Principal};
+
+/// Decides who may do what on which queue. Without any rules everyone may do
+/// anything; otherwise only what a rule allows is permitted, and anonymous
+/// callers may do nothing.
+pub struct Policy {
+    rules: Vec<PolicySettings>,
+}
+
+impl Policy {
+    /// Principal patterns have to name the credential type they apply to,
+    /// like `key:alice`, `jwt:*` or `cert:CN=client`, or be `*` to match
+    /// anyone.
+    pub fn new(rules: Vec<PolicySettings>) -> Result<Self, WebMQError> {
+        let unqualified = rules.iter().flat_map(|rule| &rule.principals).find(|pattern| {
+            pattern.as_str() != "*"
+                && !Credential::ALL
+                    .iter()
+                    .any(|credential| pattern.starts_with(credential.prefix()))
+        });
+        if let Some(pattern) = unqualified {
+            return Err(WebMQError::Config(format!(
+                "Principal {pattern} has to start with key:, jwt: or cert:"
+            )));
+        }
+
+        if !rules.is_empty() {
+            info!("Loaded {} access control rule(s)", rules.len());
+        }
+
+        Ok(Self { rules })
+    }
+
+    pub fn allows(&self, principal: Option<&Principal>, operation: QueueOperation, queue: &str) -> bool {
+        if self.rules.is_empty() {
+            return true;
+        }
+
+        let Some(principal) = principal else {
+            return false;
+        };
+
+        let principal = principal.to_string();
+        self.rules.iter().any(|rule| {
+            rule.operations.contains(&operation)
+                && rule.principals.iter().any(|pattern| matches_pattern(pattern, &principal))
+                && rule.queues.iter().any(|pattern| matches_pattern(pattern, queue))
+        })
+    }
+
+    pub fn allows_all(&self, principal: Option<&Principal>, operation: QueueOperation, queues: &[String]) -> bool {
+        queues.iter().all(|queue| self.allows(principal, operation, queue))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn principal(credential: Credential, name: &str) -> Principal {
+        Principal {
+            name: name.to_string(),
+            credential,
+        }
+    }
+
+    fn rule(principal: &str, queue: &str, operations: &[QueueOperation]) -> PolicySettings {
+        PolicySettings {
+            principals: vec![principal.to_string()],
+            queues: vec![queue.to_string()],
+            operations: operations.to_vec(),
+        }
+    }
+
+    #[test]
+    fn allows_everything_without_rules() {
+        let policy = Policy::new(vec![]).unwrap();
+        assert!(policy.allows(None, QueueOperation::Admin, "orders"));
+    }
+
+    #[test]
+    fn refuses_anonymous_callers_once_there_are_rules() {
+        let policy = Policy::new(vec![rule("*", "*", &[QueueOperation::Publish])]).unwrap();
+        assert!(!policy.allows(None, QueueOperation::Publish, "orders"));
+        assert!(policy.allows(Some(&principal(Credential::ApiKey, "alice")), QueueOperation::Publish, "orders"));
+    }
+
+    #[test]
+    fn matches_principals_within_their_credential_type() {
+        let policy = Policy::new(vec![
+            rule("key:alice", "orders.*", &[QueueOperation::Publish, QueueOperation::Consume]),
+            rule("cert:CN=*", "audit", &[QueueOperation::Admin]),
+        ])
+        .unwrap();
+
+        let alice = principal(Credential::ApiKey, "alice");
+        assert!(policy.allows(Some(&alice), QueueOperation::Consume, "orders.eu"));
+        assert!(!policy.allows(Some(&alice), QueueOperation::Admin, "orders.eu"));
+        assert!(!policy.allows(Some(&alice), QueueOperation::Publish, "audit"));
+        for credential in [Credential::Token, Credential::Certificate] {
+            let impostor = principal(credential, "alice");
+            assert!(!policy.allows(Some(&impostor), QueueOperation::Publish, "orders.eu"));
+        }
+
+        let client = principal(Credential::Certificate, "CN=client");
+        assert!(policy.allows(Some(&client), QueueOperation::Admin, "audit"));
+        assert!(!policy.allows(Some(&principal(Credential::ApiKey, "CN=client")), QueueOperation::Admin, "audit"));
+    }
+
+    #[test]
+    fn refuses_rules_with_empty_lists_or_unqualified_principals() {
+        let empty = PolicySettings {
+            principals: vec![],
+            queues: vec!["*".to_string()],
+            operations: vec![QueueOperation::Publish],
+        };
+        let policy = Policy::new(vec![empty]).unwrap();
+        assert!(!policy.allows(Some(&principal(Credential::ApiKey, "alice")), QueueOperation::Publish, "orders"));
+
+        assert!(Policy::new(vec![rule("alice", "*", &[QueueOperation::Publish])]).is_err());
+    }
+}
@@ -0,0 +1,69 @@
+const DEFAULT_PRINCIPAL_CLAIM: &str = "sub";
+
+/// Leaving out both API keys and JWT settings lets anyone in. Unknown keys are
+/// refused, so a typo can't quietly turn authentication off.
+#[derive(Debug, serde::Deserialize, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct AuthSettings {
+    #[serde(default)]
+    pub api_keys: Vec<ApiKeySettings>,
+    #[serde(default)]
+    pub jwt: Option<JwtSettings>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ApiKeySettings {
+    /// Name the requests carrying this key are made under.
+    pub principal: String,
+    pub key_file: String,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct JwtSettings {
+    pub algorithm: JwtAlgorithm,
+    /// The shared secret for HS256, or a PEM encoded public key for RS256.
+    pub key_file: String,
+    #[serde(default)]
+    pub issuer: Option<String>,
+    #[serde(default)]
+    pub audience: Option<String>,
+    /// Claim holding the name requests are made under.
+    #[serde(default = "JwtSettings::default_principal_claim")]
+    pub principal_claim: String,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq)]
+pub enum JwtAlgorithm {
+    #[serde(alias = "hs256")]
+    HS256,
+    #[serde(alias = "rs256")]
+    RS256,
+}
+
+impl JwtSettings {
+    fn default_principal_claim() -> String {
+        DEFAULT_PRINCIPAL_CLAIM.to_string()
+    }
+}
+
+/// Lets `principals` perform `operations` on the queues matching `queues`.
+/// Patterns may use `*` for any number of characters and `?` for one.
+/// Principals are matched along with the type of credential they used, as in
+/// `key:alice`, `jwt:alice` or `cert:CN=alice`.
+#[derive(Debug, serde::Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PolicySettings {
+    pub principals: Vec<String>,
+    pub queues: Vec<String>,
+    pub operations: Vec<QueueOperation>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum QueueOperation {
+    Publish,
+    Consume,
+    Admin,
+}
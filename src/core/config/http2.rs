@@ -0,0 +1,39 @@
+const DEFAULT_MAX_CONCURRENT_STREAMS: u32 = 200;
+const DEFAULT_INITIAL_STREAM_WINDOW_SIZE: u32 = 1024 * 1024;
+const DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE: u32 = 1024 * 1024;
+
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct Http2Settings {
+    /// How many requests a single client may have in flight on one
+    /// connection.
+    #[serde(default = "Http2Settings::default_max_concurrent_streams")]
+    pub max_concurrent_streams: u32,
+    #[serde(default = "Http2Settings::default_initial_stream_window_size")]
+    pub initial_stream_window_size: u32,
+    #[serde(default = "Http2Settings::default_initial_connection_window_size")]
+    pub initial_connection_window_size: u32,
+}
+
+impl Http2Settings {
+    fn default_max_concurrent_streams() -> u32 {
+        DEFAULT_MAX_CONCURRENT_STREAMS
+    }
+
+    fn default_initial_stream_window_size() -> u32 {
+        DEFAULT_INITIAL_STREAM_WINDOW_SIZE
+    }
+
+    fn default_initial_connection_window_size() -> u32 {
+        DEFAULT_INITIAL_CONNECTION_WINDOW_SIZE
+    }
+}
+
+impl Default for Http2Settings {
+    fn default() -> Self {
+        Self {
+            max_concurrent_streams: Self::default_max_concurrent_streams(),
+            initial_stream_window_size: Self::default_initial_stream_window_size(),
+            initial_connection_window_size: Self::default_initial_connection_window_size(),
+        }
+    }
+}
@@ -1,5 +1,11 @@
-use super::network::NetworkSettings;
-use config::Config;
+use std::collections::HashMap;
+
+use super::{
+    auth::{AuthSettings, PolicySettings}, messaging::MessagingSettings, network::NetworkSettings, queue::QueueSettings,
+    storage::StorageSettings,
+};
+use crate::core::errors::WebMQError;
+use config::{Config, ConfigError};
 use log::{info, warn};
 
 const CONFIGURATION_FILE: &str = "./configuration";
@@ -8,38 +14,52 @@ const CONFIGURATION_FILE: &str = "./configuration";
 pub struct Settings {
     #[serde(default = "NetworkSettings::default")]
     pub network: NetworkSettings,
+    #[serde(default = "StorageSettings::default")]
+    pub storage: StorageSettings,
+    #[serde(default = "MessagingSettings::default")]
+    pub messaging: MessagingSettings,
+    #[serde(default)]
+    pub auth: AuthSettings,
+    /// Access control rules. Leaving them out allows everything.
+    #[serde(default)]
+    pub policies: Vec<PolicySettings>,
+    #[serde(default)]
+    pub queues: HashMap<String, QueueSettings>,
+    /// Subscription queues bound to each topic at startup.
+    #[serde(default)]
+    pub topics: HashMap<String, Vec<String>>,
 }
 
 impl Settings {
-    pub fn load() -> Self {
+    /// Falls back to the defaults when there is no configuration file, but
+    /// refuses one that can't be parsed. Running with defaults instead would
+    /// silently drop its authentication and access policies.
+    pub fn load() -> Result<Self, WebMQError> {
         let raw_config = Config::builder()
             .add_source(config::File::with_name(CONFIGURATION_FILE))
             .build();
 
         match raw_config {
             Ok(config) => {
-                let c = Self::try_deserialize_config(config);
+                let c = Self::try_deserialize_config(config)?;
                 info!("Loaded configuration from {CONFIGURATION_FILE}");
-                c
+                Ok(c)
             }
+            Err(error @ ConfigError::FileParse { .. }) => Err(WebMQError::Config(format!(
+                "Failed to parse system configuration: {error}"
+            ))),
             Err(error) => {
                 warn!(
                     "Failed to load system configuration: {error}. Attempting to fall back to defaults."
                 );
-                Self::default()
+                Ok(Self::default())
             }
         }
     }
 
-    fn try_deserialize_config(config: Config) -> Self {
-        match config.try_deserialize() {
-            Ok(config) => config,
-            Err(error) => {
-                warn!(
-                    "Failed to parse system configuration: {error}. Attempting to fall back to defaults."
-                );
-                Self::default()
-            }
-        }
+    fn try_deserialize_config(config: Config) -> Result<Self, WebMQError> {
+        config
+            .try_deserialize()
+            .map_err(|error| WebMQError::Config(format!("Failed to parse system configuration: {error}")))
     }
 }
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+const DEFAULT_MAX_WAIT_SECONDS: u64 = 60;
+const DEFAULT_VISIBILITY_TIMEOUT_SECONDS: u64 = 30;
+const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_BATCH_SIZE: usize = 16 * 1024 * 1024;
+const DEFAULT_EXPIRY_SWEEP_INTERVAL_SECONDS: u64 = 30;
+
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct MessagingSettings {
+    /// Upper bound for how long a consumer may long-poll an empty queue.
+    #[serde(default = "MessagingSettings::default_max_wait_seconds")]
+    pub max_wait_seconds: u64,
+    /// How long a consumed message stays hidden before it is redelivered
+    /// unless it gets acknowledged.
+    #[serde(default = "MessagingSettings::default_visibility_timeout_seconds")]
+    pub visibility_timeout_seconds: u64,
+    /// Largest message body in bytes accepted by any queue.
+    #[serde(default = "MessagingSettings::default_max_message_size")]
+    pub max_message_size: usize,
+    /// Largest request body in bytes accepted when publishing a batch.
+    #[serde(default = "MessagingSettings::default_max_batch_size")]
+    pub max_batch_size: usize,
+    /// How often queues are checked for expired messages. Set to 0 to only
+    /// drop them when they are about to be consumed.
+    #[serde(default = "MessagingSettings::default_expiry_sweep_interval_seconds")]
+    pub expiry_sweep_interval_seconds: u64,
+}
+
+impl MessagingSettings {
+    fn default_max_wait_seconds() -> u64 {
+        DEFAULT_MAX_WAIT_SECONDS
+    }
+
+    fn default_visibility_timeout_seconds() -> u64 {
+        DEFAULT_VISIBILITY_TIMEOUT_SECONDS
+    }
+
+    fn default_max_message_size() -> usize {
+        DEFAULT_MAX_MESSAGE_SIZE
+    }
+
+    fn default_max_batch_size() -> usize {
+        DEFAULT_MAX_BATCH_SIZE
+    }
+
+    fn default_expiry_sweep_interval_seconds() -> u64 {
+        DEFAULT_EXPIRY_SWEEP_INTERVAL_SECONDS
+    }
+
+    pub fn max_wait(&self) -> Duration {
+        Duration::from_secs(self.max_wait_seconds)
+    }
+
+    pub fn visibility_timeout(&self) -> Duration {
+        Duration::from_secs(self.visibility_timeout_seconds)
+    }
+
+    pub fn expiry_sweep_interval(&self) -> Option<Duration> {
+        match self.expiry_sweep_interval_seconds {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
+    }
+}
+
+impl Default for MessagingSettings {
+    fn default() -> Self {
+        Self {
+            max_wait_seconds: Self::default_max_wait_seconds(),
+            visibility_timeout_seconds: Self::default_visibility_timeout_seconds(),
+            max_message_size: Self::default_max_message_size(),
+            max_batch_size: Self::default_max_batch_size(),
+            expiry_sweep_interval_seconds: Self::default_expiry_sweep_interval_seconds(),
+        }
+    }
+}
@@ -1,3 +1,8 @@
+pub mod auth;
+pub mod http2;
 pub mod main;
+pub mod messaging;
 pub mod network;
+pub mod queue;
+pub mod storage;
 pub mod tls;
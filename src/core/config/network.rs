@@ -1,21 +1,89 @@
-use crate::core::config::tls::TlsSettings;
+use log::warn;
 
-const DEFAULT_IP: &str = "0.0.0.0";
+use crate::core::config::{http2::Http2Settings, tls::TlsSettings};
+
+const DEFAULT_ADDRESS: &str = "0.0.0.0";
 const DEFAULT_PORT: u16 = 8060;
 
 #[derive(Debug, serde::Deserialize)]
+#[serde(try_from = "RawNetworkSettings")]
 pub struct NetworkSettings {
-    #[serde(default = "NetworkSettings::default_ip")]
-    pub ip: String,
-    #[serde(default = "NetworkSettings::default_port")]
+    /// Every address the broker accepts connections on. All of them serve the
+    /// same queues.
+    #[serde(default = "NetworkSettings::default_listeners")]
+    pub listeners: Vec<ListenerSettings>,
+    #[serde(default = "Http2Settings::default")]
+    pub http2: Http2Settings,
+}
+
+/// `network` as written, including the single listener keys which predate
+/// `listeners`.
+#[derive(serde::Deserialize)]
+struct RawNetworkSettings {
+    #[serde(default)]
+    listeners: Option<Vec<ListenerSettings>>,
+    #[serde(default = "Http2Settings::default")]
+    http2: Http2Settings,
+    #[serde(default)]
+    ip: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    tls: Option<TlsSettings>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ListenerSettings {
+    /// IP addresses or host names to bind to. Host names are resolved at
+    /// startup and bound on every address they resolve to.
+    #[serde(default = "ListenerSettings::default_addresses")]
+    pub addresses: Vec<String>,
+    #[serde(default = "ListenerSettings::default_port")]
     pub port: u16,
-    #[serde(default = "TlsSettings::default")]
-    pub tls: TlsSettings,
+    /// Serves plain HTTP when left out.
+    #[serde(default)]
+    pub tls: Option<TlsSettings>,
 }
 
 impl NetworkSettings {
-    fn default_ip() -> String {
-        DEFAULT_IP.to_string()
+    fn default_listeners() -> Vec<ListenerSettings> {
+        vec![ListenerSettings::default()]
+    }
+}
+
+impl TryFrom<RawNetworkSettings> for NetworkSettings {
+    type Error = String;
+
+    fn try_from(raw: RawNetworkSettings) -> Result<Self, Self::Error> {
+        let legacy = raw.ip.is_some() || raw.port.is_some() || raw.tls.is_some();
+        let listeners = match (raw.listeners, legacy) {
+            (Some(_), true) => {
+                return Err(
+                    "network.ip, network.port and network.tls can't be combined with network.listeners".to_string(),
+                );
+            }
+            (Some(listeners), false) => listeners,
+            (None, true) => {
+                warn!("network.ip, network.port and network.tls are deprecated, use network.listeners instead");
+                vec![ListenerSettings {
+                    addresses: raw.ip.map_or_else(ListenerSettings::default_addresses, |ip| vec![ip]),
+                    port: raw.port.unwrap_or(DEFAULT_PORT),
+                    tls: Some(raw.tls.unwrap_or_default()),
+                }]
+            }
+            (None, false) => Self::default_listeners(),
+        };
+
+        Ok(Self {
+            listeners,
+            http2: raw.http2,
+        })
+    }
+}
+
+impl ListenerSettings {
+    fn default_addresses() -> Vec<String> {
+        vec![DEFAULT_ADDRESS.to_string()]
     }
 
     fn default_port() -> u16 {
@@ -26,9 +94,18 @@ impl NetworkSettings {
 impl Default for NetworkSettings {
     fn default() -> Self {
         Self {
-            ip: Self::default_ip(),
+            listeners: Self::default_listeners(),
+            http2: Http2Settings::default(),
+        }
+    }
+}
+
+impl Default for ListenerSettings {
+    fn default() -> Self {
+        Self {
+            addresses: Self::default_addresses(),
             port: Self::default_port(),
-            tls: TlsSettings::default(),
+            tls: Some(TlsSettings::default()),
         }
     }
 }
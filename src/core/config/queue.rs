@@ -0,0 +1,42 @@
+use std::time::Duration;
+
+use crate::core::errors::WebMQError;
+
+#[derive(Debug, serde::Deserialize, serde::Serialize, Clone, Default)]
+pub struct QueueSettings {
+    /// Number of deliveries after which a message stops being redelivered.
+    #[serde(default)]
+    pub max_deliveries: Option<u32>,
+    /// Queue receiving messages that exceeded `max_deliveries`. Without one
+    /// such messages are dropped.
+    #[serde(default)]
+    pub dead_letter_queue: Option<String>,
+    /// Overrides the broker wide maximum message size for this queue.
+    #[serde(default)]
+    pub max_message_size: Option<usize>,
+    /// How long messages published without a TTL of their own live for.
+    #[serde(default)]
+    pub ttl_seconds: Option<u64>,
+    /// Queue receiving messages that expired. Without one they are dropped.
+    #[serde(default)]
+    pub expiry_queue: Option<String>,
+}
+
+impl QueueSettings {
+    pub fn ttl(&self) -> Option<Duration> {
+        self.ttl_seconds.map(Duration::from_secs)
+    }
+
+    /// Refuses settings which would move messages from `queue` back into
+    /// itself.
+    pub fn validate(&self, queue: &str) -> Result<(), WebMQError> {
+        if self.dead_letter_queue.as_deref() == Some(queue) {
+            return Err(WebMQError::Config(format!("Queue {queue} can't be its own dead letter queue")));
+        }
+        if self.expiry_queue.as_deref() == Some(queue) {
+            return Err(WebMQError::Config(format!("Queue {queue} can't be its own expiry queue")));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+const DEFAULT_DIRECTORY: &str = "./data";
+const DEFAULT_SEGMENT_SIZE: u64 = 64 * 1024 * 1024;
+const DEFAULT_FSYNC_BATCH_SIZE: usize = 64;
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    Memory,
+    File,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    /// Sync every write before acknowledging it.
+    Always,
+    /// Sync once every `fsync_batch_size` writes.
+    Batch,
+    /// Leave flushing to the operating system.
+    Never,
+}
+
+/// Where queued messages are kept. Only messages waiting in a queue are
+/// stored; deliveries which haven't been acknowledged yet live in memory and
+/// don't survive a restart.
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct StorageSettings {
+    #[serde(default = "StorageSettings::default_backend")]
+    pub backend: StorageBackend,
+    #[serde(default = "StorageSettings::default_directory")]
+    pub directory: String,
+    #[serde(default = "StorageSettings::default_segment_size")]
+    pub segment_size: u64,
+    #[serde(default = "StorageSettings::default_fsync")]
+    pub fsync: FsyncPolicy,
+    #[serde(default = "StorageSettings::default_fsync_batch_size")]
+    pub fsync_batch_size: usize,
+}
+
+impl StorageSettings {
+    fn default_backend() -> StorageBackend {
+        StorageBackend::Memory
+    }
+
+    fn default_directory() -> String {
+        DEFAULT_DIRECTORY.to_string()
+    }
+
+    fn default_segment_size() -> u64 {
+        DEFAULT_SEGMENT_SIZE
+    }
+
+    fn default_fsync() -> FsyncPolicy {
+        FsyncPolicy::Always
+    }
+
+    fn default_fsync_batch_size() -> usize {
+        DEFAULT_FSYNC_BATCH_SIZE
+    }
+}
+
+impl Default for StorageSettings {
+    fn default() -> Self {
+        Self {
+            backend: Self::default_backend(),
+            directory: Self::default_directory(),
+            segment_size: Self::default_segment_size(),
+            fsync: Self::default_fsync(),
+            fsync_batch_size: Self::default_fsync_batch_size(),
+        }
+    }
+}
@@ -1,11 +1,51 @@
-#[derive(Debug, serde::Deserialize)]
+use std::{collections::HashMap, time::Duration};
+
+const DEFAULT_RELOAD_INTERVAL_SECONDS: u64 = 60;
+
+#[derive(Debug, serde::Deserialize, Clone)]
 pub struct TlsSettings {
     #[serde(default = "TlsSettings::default_certificate")]
     pub certificate: String,
     #[serde(default = "TlsSettings::default_private_key")]
     pub private_key: String,
+    /// Algorithm the private key is expected to use. Detected from the key
+    /// itself when set to `auto`.
+    #[serde(default = "TlsSettings::default_algorithm")]
+    pub algorithm: KeyAlgorithm,
+    /// Certificates to present instead of the default one to clients asking
+    /// for one of these server names. Names may start with a `*.` wildcard.
+    #[serde(default)]
+    pub server_names: HashMap<String, CertificateSettings>,
+    /// How often to check the certificate and private key for changes. Set
+    /// to 0 to only reload them on SIGHUP.
+    #[serde(default = "TlsSettings::default_reload_interval_seconds")]
+    pub reload_interval_seconds: u64,
+    /// What to do with clients which don't speak TLS on this listener.
+    #[serde(default = "TlsSettings::default_plaintext")]
+    pub plaintext: PlaintextMode,
+    /// Verifies client certificates when set.
+    #[serde(default)]
+    pub client_auth: Option<ClientAuthSettings>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct CertificateSettings {
+    pub certificate: String,
+    pub private_key: String,
     #[serde(default = "TlsSettings::default_algorithm")]
-    pub algorithm: String,
+    pub algorithm: KeyAlgorithm,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAlgorithm {
+    Auto,
+    #[serde(alias = "RSA")]
+    Rsa,
+    #[serde(alias = "ECDSA")]
+    Ecdsa,
+    #[serde(alias = "ED25519", alias = "Ed25519")]
+    Ed25519,
 }
 
 impl TlsSettings {
@@ -17,8 +57,23 @@ impl TlsSettings {
         "./key.pem".to_string()
     }
 
-    fn default_algorithm() -> String {
-        "RSA".to_string()
+    fn default_algorithm() -> KeyAlgorithm {
+        KeyAlgorithm::Auto
+    }
+
+    fn default_reload_interval_seconds() -> u64 {
+        DEFAULT_RELOAD_INTERVAL_SECONDS
+    }
+
+    fn default_plaintext() -> PlaintextMode {
+        PlaintextMode::Reject
+    }
+
+    pub fn reload_interval(&self) -> Option<Duration> {
+        match self.reload_interval_seconds {
+            0 => None,
+            seconds => Some(Duration::from_secs(seconds)),
+        }
     }
 }
 
@@ -28,6 +83,48 @@ impl Default for TlsSettings {
             certificate: Self::default_certificate(),
             private_key: Self::default_private_key(),
             algorithm: Self::default_algorithm(),
+            server_names: HashMap::new(),
+            reload_interval_seconds: Self::default_reload_interval_seconds(),
+            plaintext: Self::default_plaintext(),
+            client_auth: None,
         }
     }
 }
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PlaintextMode {
+    /// Closes the connection.
+    Reject,
+    /// Serves plain HTTP like a listener without TLS would.
+    Serve,
+    /// Answers every request with a redirect to HTTPS.
+    Redirect,
+}
+
+#[derive(Debug, serde::Deserialize, Clone)]
+pub struct ClientAuthSettings {
+    /// PEM bundle or DER file with the CA certificates client certificates
+    /// have to be issued by.
+    pub ca_certificates: String,
+    #[serde(default = "ClientAuthSettings::default_mode")]
+    pub mode: ClientAuthMode,
+    #[serde(default)]
+    pub revocation_lists: Vec<String>,
+}
+
+#[derive(Debug, serde::Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientAuthMode {
+    /// Refuses handshakes without a valid client certificate.
+    Required,
+    /// Verifies client certificates when presented, but lets anonymous
+    /// clients connect too.
+    Optional,
+}
+
+impl ClientAuthSettings {
+    fn default_mode() -> ClientAuthMode {
+        ClientAuthMode::Required
+    }
+}
@@ -6,6 +6,7 @@ pub enum WebMQError {
     File(String),
     TLS(String),
     Data(String),
+    Auth(String),
     Unrecoverable,
 }
 
@@ -23,6 +24,7 @@ impl WebMQError {
             WebMQError::File(msg) => msg.as_str(),
             WebMQError::TLS(msg) => msg.as_str(),
             WebMQError::Data(msg) => msg.as_str(),
+            WebMQError::Auth(msg) => msg.as_str(),
             WebMQError::Unrecoverable => "The program encountered an unrecoverable error.",
         }
     }
@@ -0,0 +1,14 @@
+/// A message handed out to a consumer, along with the receipt it has to
+/// present to acknowledge it.
+pub struct Delivery<D> {
+    pub receipt: String,
+    pub data: D,
+}
+
+/// An item taken out of a queue which stays in storage until it is released,
+/// so it isn't lost if the broker stops before it has been dealt with.
+#[derive(Clone)]
+pub struct Reserved<T> {
+    pub token: u64,
+    pub data: T,
+}
@@ -0,0 +1,15 @@
+use std::fmt::Display;
+
+/// Who is on the other end of a connection, as vouched for by a verified
+/// client certificate.
+#[derive(Debug, Clone)]
+pub struct ClientIdentity {
+    pub subject: String,
+    pub alternative_names: Vec<String>,
+}
+
+impl Display for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.subject)
+    }
+}
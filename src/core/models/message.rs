@@ -0,0 +1,90 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::core::traits::{ByteSize, Expiring};
+
+/// A message as it is stored in a queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    /// Assigned by the broker when the message is published.
+    pub id: String,
+    /// Milliseconds since the Unix epoch at which the message was published.
+    pub timestamp: u64,
+    pub content_type: Option<String>,
+    /// Headers set by the publisher, handed back to consumers untouched.
+    pub headers: BTreeMap<String, String>,
+    pub data: Vec<u8>,
+    /// Number of times the message has been handed out to a consumer.
+    pub delivery_count: u32,
+    pub failures: Vec<DeliveryFailure>,
+    /// Queue the message was dead-lettered from, if any.
+    pub dead_letter_source: Option<String>,
+    /// Milliseconds since the Unix epoch after which the message is dropped
+    /// instead of being delivered.
+    pub expires_at: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeliveryFailure {
+    /// Milliseconds since the Unix epoch.
+    pub timestamp: u64,
+    pub reason: String,
+}
+
+impl Message {
+    pub fn new(data: Vec<u8>) -> Message {
+        Message {
+            id: Uuid::new_v4().to_string(),
+            timestamp: unix_millis(),
+            content_type: None,
+            headers: BTreeMap::new(),
+            data,
+            delivery_count: 0,
+            failures: vec![],
+            dead_letter_source: None,
+            expires_at: None,
+        }
+    }
+
+    /// Lets the message live for `ttl` after it was published.
+    pub fn expire_after(&mut self, ttl: Duration) {
+        self.expires_at = Some(self.timestamp.saturating_add(ttl.as_millis() as u64));
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+impl DeliveryFailure {
+    pub fn now(reason: String) -> DeliveryFailure {
+        DeliveryFailure {
+            timestamp: unix_millis(),
+            reason,
+        }
+    }
+}
+
+pub fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+impl ByteSize for Message {
+    fn byte_size(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl Expiring for Message {
+    fn expires_at(&self) -> Option<u64> {
+        self.expires_at
+    }
+}
@@ -1 +1,4 @@
-pub mod pubish_message;
\ No newline at end of file
+pub mod delivery;
+pub mod identity;
+pub mod message;
+pub mod queue_info;
\ No newline at end of file
@@ -1,3 +0,0 @@
-pub struct PublishMessage<'a> {
-    data: &'a [u8]
-}
\ No newline at end of file
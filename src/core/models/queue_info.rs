@@ -0,0 +1,23 @@
+use serde::Serialize;
+
+use crate::core::config::queue::QueueSettings;
+
+/// How much a queue is holding, not counting messages handed out to
+/// consumers.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct QueueStats {
+    pub messages: usize,
+    /// Combined size of the queued messages.
+    pub bytes: usize,
+}
+
+/// A queue as reported by the admin API.
+#[derive(Debug, Clone, Serialize)]
+pub struct QueueInfo {
+    pub name: String,
+    #[serde(flatten)]
+    pub stats: QueueStats,
+    /// Messages handed out to consumers which haven't been settled yet.
+    pub in_flight: usize,
+    pub settings: QueueSettings,
+}
@@ -1,8 +1,13 @@
-use std::error::Error;
+use std::{error::Error, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
+use tokio::sync::Notify;
 
-use super::errors::WebMQError;
+use super::{
+    config::queue::QueueSettings,
+    errors::WebMQError,
+    models::{delivery::{Delivery, Reserved}, queue_info::{QueueInfo, QueueStats}},
+};
 #[async_trait]
 pub trait AsyncStart {
     async fn start(&self);
@@ -17,14 +22,110 @@ pub trait Adapter {
 }
 
 
+/// Something whose size counts against a byte budget.
+pub trait ByteSize {
+    fn byte_size(&self) -> usize;
+}
+
+/// Something a durable queue can store. Stored records are tagged with the
+/// `FORMAT_VERSION` they were written in, so they can still be read after the
+/// format changes.
+pub trait Persistent: Sized {
+    const FORMAT_VERSION: u8;
+
+    fn encode(&self) -> Result<Vec<u8>, String>;
+    /// Decodes a record written in format `version`, which is `None` for
+    /// records written before they were tagged.
+    fn decode(version: Option<u8>, payload: &[u8]) -> Result<Self, String>;
+}
+
+/// Something which may expire.
+pub trait Expiring {
+    /// Unix time in milliseconds after which it is expired, if ever.
+    fn expires_at(&self) -> Option<u64>;
+}
+
 #[async_trait]
 pub trait AsyncQueue<T> {
     async fn pop(&mut self) -> Result<T, Box<dyn Error>>;
+    /// Pops up to `max` items in order, stopping before the first one which
+    /// would take them past `max_bytes`. The first item is always popped,
+    /// however large it is.
+    async fn pop_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<T>, Box<dyn Error>>;
+    /// Takes items like `pop_many`, but leaves them in storage until they are
+    /// released. Durable queues hand out whatever wasn't released again once
+    /// they are reopened.
+    async fn reserve_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<Reserved<T>>, Box<dyn Error>>;
+    /// Drops reserved items from storage for good.
+    async fn release(&mut self, tokens: Vec<u64>) -> Option<Box<dyn Error>>;
+    /// Reserves every item which expired by `now`, wherever it is in the queue.
+    async fn reserve_expired(&mut self, now: u64) -> Result<Vec<Reserved<T>>, Box<dyn Error>>;
+    /// Returns the item `pop` would return next, without removing it.
+    async fn peek(&self) -> Result<T, Box<dyn Error>>;
+    /// Returns up to `limit` items starting `offset` items into the queue,
+    /// without removing them.
+    async fn browse(&self, offset: usize, limit: usize) -> Result<Vec<T>, Box<dyn Error>>;
     async fn push(&mut self, data: T) -> Option<Box<dyn Error>>;
+    /// Pushes every item in `data` in order, or none of them if any fails.
+    async fn push_many(&mut self, data: Vec<T>) -> Option<Box<dyn Error>>;
+    fn stats(&self) -> QueueStats;
+    /// Drops everything in the queue, returning how many items were dropped.
+    async fn purge(&mut self) -> Result<usize, Box<dyn Error>>;
+    /// Removes the queue from storage. It can't be used anymore afterwards.
+    async fn delete(&mut self) -> Option<Box<dyn Error>>;
 }
 
 #[async_trait]
 pub trait MessagingDispatcher<Q, D> {
-    async fn publish(&mut self, queue: Q, data: D) -> Option<WebMQError>;
-    async fn consume(&mut self, queue: Q) -> Result<D, WebMQError>;
+    async fn publish(&self, queue: Q, data: D) -> Option<WebMQError>;
+    /// Publishes all of `data` at once. Consumers see either the whole batch
+    /// or none of it.
+    async fn publish_batch(&self, queue: Q, data: Vec<D>) -> Option<WebMQError>;
+    /// Hands out the next message in `queue`, hiding it from other consumers
+    /// until it is acknowledged or `visibility_timeout` passes.
+    async fn consume(&self, queue: Q, visibility_timeout: Duration) -> Result<Delivery<D>, WebMQError>;
+    /// Hands out up to `max` messages from `queue` at once, as long as they
+    /// add up to no more than `max_bytes`.
+    async fn consume_many(
+        &self,
+        queue: Q,
+        max: usize,
+        max_bytes: usize,
+        visibility_timeout: Duration,
+    ) -> Result<Vec<Delivery<D>>, WebMQError>;
+    async fn ack(&self, queue: Q, receipt: String) -> Option<WebMQError>;
+    async fn nack(&self, queue: Q, receipt: String) -> Option<WebMQError>;
+    /// Reports that the consumer failed to process a delivery. The failure is
+    /// recorded on the message before it is redelivered or dead-lettered.
+    async fn reject(&self, queue: Q, receipt: String, reason: Option<String>) -> Option<WebMQError>;
+    /// Puts a delivery which never reached its consumer back into the queue,
+    /// without counting it as a delivery attempt.
+    async fn return_delivery(&self, queue: Q, receipt: String) -> Option<WebMQError>;
+    /// Moves every message in a dead letter queue back to the queue it came
+    /// from, returning how many were moved.
+    async fn redrive(&self, queue: Q) -> Result<usize, WebMQError>;
+    /// Copies a message into every queue subscribed to `topic`, returning how
+    /// many subscriptions received it.
+    async fn publish_topic(&self, topic: Q, data: D) -> Result<usize, WebMQError>;
+    async fn subscribe(&self, topic: Q, queue: Q) -> Option<WebMQError>;
+    async fn unsubscribe(&self, topic: Q, queue: Q) -> Option<WebMQError>;
+    async fn subscriptions(&self, topic: Q) -> Vec<Q>;
+    /// Returns a handle which is notified whenever a message is published to
+    /// `queue`. Consumers should start watching before they try to consume, so
+    /// nothing published in between goes unnoticed.
+    async fn watch(&self, queue: Q) -> Arc<Notify>;
+    async fn queues(&self) -> Vec<Q>;
+    async fn queue_info(&self, queue: Q) -> Result<QueueInfo, WebMQError>;
+    /// Creates `queue` with `settings`, or applies them to it if it already
+    /// exists. Returns whether the queue was created.
+    async fn create_queue(&self, queue: Q, settings: QueueSettings) -> Result<bool, WebMQError>;
+    /// Removes `queue` along with every message in it, including those handed
+    /// out to consumers.
+    async fn delete_queue(&self, queue: Q) -> Option<WebMQError>;
+    /// Drops every message waiting in `queue`, returning how many were
+    /// dropped. Messages handed out to consumers are left alone.
+    async fn purge_queue(&self, queue: Q) -> Result<usize, WebMQError>;
+    /// Returns up to `limit` of the messages waiting in `queue`, starting
+    /// `offset` messages in, without consuming them.
+    async fn browse(&self, queue: Q, offset: usize, limit: usize) -> Result<Vec<D>, WebMQError>;
 }
\ No newline at end of file
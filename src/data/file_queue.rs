@@ -0,0 +1,1123 @@
+use std::{
+    collections::{BTreeSet, HashMap, VecDeque},
+    error::Error,
+    fs::{self, File, OpenOptions},
+    io::{ErrorKind, Read, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use log::{info, warn};
+
+use crate::core::{
+    config::storage::{FsyncPolicy, StorageSettings},
+    errors::WebMQError,
+    models::{delivery::Reserved, queue_info::QueueStats},
+    traits::{AsyncQueue, ByteSize, Expiring, Persistent},
+};
+
+const SEGMENT_EXTENSION: &str = "seg";
+const HEAD_FILE: &str = "head";
+const HEAD_TEMP_FILE: &str = "head.tmp";
+const CORRUPT_FILE: &str = "corrupt";
+const RECORD_HEADER_SIZE: u64 = 8;
+/// Set in the length of records which start with their format version.
+const TAGGED_RECORD: u32 = 1 << 31;
+
+/// Location of a single record inside the segmented log.
+#[derive(Debug, Clone, Copy)]
+struct RecordPosition {
+    segment: u64,
+    offset: u64,
+    length: u32,
+    /// When the record expires, so expired records can be found without
+    /// reading every one of them.
+    expires_at: Option<u64>,
+}
+
+impl RecordPosition {
+    fn end(&self) -> u64 {
+        self.offset + RECORD_HEADER_SIZE + self.length as u64
+    }
+}
+
+/// A record as read back from a segment.
+struct StoredRecord {
+    /// Format the payload was written in, or `None` for records written
+    /// before they carried one.
+    version: Option<u8>,
+    payload: Vec<u8>,
+    /// Length of the record on disk, without its header.
+    length: u32,
+}
+
+struct ActiveSegment {
+    id: u64,
+    file: File,
+    length: u64,
+}
+
+/// Durable queue stored as a segmented append-only log.
+///
+/// Every record is written as `[length: u32][crc32: u32][version: u8][payload]`,
+/// with the top bit of the length set to tell it apart from records written
+/// before they carried the format version of their payload. The
+/// position of the oldest record which hasn't been released yet is kept in a
+/// separate `head` file which is replaced atomically whenever it moves, and
+/// segments are deleted once the head moves past them. Reserved records stay
+/// in the log, so on open everything from the head is visible again. That
+/// includes records released out of order behind one which wasn't, which are
+/// delivered a second time. On open the log is also scanned for any torn or
+/// corrupted tail left behind by a crash, which is truncated. Records which
+/// turn out to be corrupted later on are copied to a `corrupt` file and
+/// skipped.
+///
+/// Once the queue is open, all file access runs on the blocking thread pool.
+pub struct FileQueue<T> {
+    log: Arc<Mutex<SegmentLog>>,
+    _data: PhantomData<fn() -> T>,
+}
+
+/// The files behind a [`FileQueue`].
+struct SegmentLog {
+    directory: PathBuf,
+    segment_size: u64,
+    fsync: FsyncPolicy,
+    fsync_batch_size: usize,
+    unsynced_writes: usize,
+    writer: ActiveSegment,
+    records: VecDeque<RecordPosition>,
+    /// Records which were reserved but haven't been released yet, by token.
+    reserved: HashMap<u64, RecordPosition>,
+    /// Where the reserved records are, so the oldest one can hold the head.
+    held: BTreeSet<(u64, u64)>,
+    next_token: u64,
+    head: (u64, u64),
+}
+
+#[async_trait]
+impl<T: Persistent + ByteSize + Expiring + Send + Sync + 'static> AsyncQueue<T> for FileQueue<T> {
+    async fn pop(&mut self) -> Result<T, Box<dyn Error>> {
+        let mut items = self.pop_many(1, usize::MAX).await?;
+        Ok(items.remove(0))
+    }
+
+    async fn pop_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<T>, Box<dyn Error>> {
+        Ok(self.blocking(move |log| log.pop_many(max, max_bytes)).await?)
+    }
+
+    async fn reserve_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<Reserved<T>>, Box<dyn Error>> {
+        Ok(self.blocking(move |log| log.reserve_many(max, max_bytes)).await?)
+    }
+
+    async fn release(&mut self, tokens: Vec<u64>) -> Option<Box<dyn Error>> {
+        match self.blocking(move |log| log.release(&tokens)).await {
+            Ok(()) => None,
+            Err(e) => Some(Box::new(e)),
+        }
+    }
+
+    async fn reserve_expired(&mut self, now: u64) -> Result<Vec<Reserved<T>>, Box<dyn Error>> {
+        Ok(self.blocking(move |log| log.reserve_expired(now)).await?)
+    }
+
+    async fn peek(&self) -> Result<T, Box<dyn Error>> {
+        let mut items = self.browse(0, 1).await?;
+        if items.is_empty() {
+            return Err(Box::new(WebMQError::Data(
+                "Failed to peek at queue as it contains no elements".into(),
+            )));
+        }
+
+        Ok(items.remove(0))
+    }
+
+    async fn browse(&self, offset: usize, limit: usize) -> Result<Vec<T>, Box<dyn Error>> {
+        Ok(self.blocking(move |log| log.browse(offset, limit)).await?)
+    }
+
+    async fn push(&mut self, data: T) -> Option<Box<dyn Error>> {
+        self.push_many(vec![data]).await
+    }
+
+    async fn push_many(&mut self, data: Vec<T>) -> Option<Box<dyn Error>> {
+        let res = self.blocking(move |log| {
+            let positions = log.append_records(&data)?;
+            log.records.extend(positions);
+            Ok(())
+        });
+        match res.await {
+            Ok(()) => None,
+            Err(e) => Some(Box::new(e)),
+        }
+    }
+
+    /// Sizes are those of the stored records, which include the encoded
+    /// message metadata.
+    fn stats(&self) -> QueueStats {
+        match self.log.lock() {
+            Ok(log) => QueueStats {
+                messages: log.records.len(),
+                bytes: log.records.iter().map(|position| position.length as usize).sum(),
+            },
+            Err(_) => QueueStats::default(),
+        }
+    }
+
+    async fn purge(&mut self) -> Result<usize, Box<dyn Error>> {
+        let purged = self.blocking(|log| {
+            let consumed: Vec<RecordPosition> = log.records.drain(..).collect();
+            if let Err(e) = log.update_head() {
+                log.records.extend(consumed);
+                return Err(e);
+            }
+
+            Ok(consumed.len())
+        });
+
+        Ok(purged.await?)
+    }
+
+    async fn delete(&mut self) -> Option<Box<dyn Error>> {
+        let res = self.blocking(|log| match fs::remove_dir_all(&log.directory) {
+            Ok(_) => {
+                log.records.clear();
+                log.reserved.clear();
+                log.held.clear();
+                Ok(())
+            }
+            Err(e) => Err(file_error(&log.directory, "remove queue directory", e)),
+        });
+        match res.await {
+            Ok(()) => None,
+            Err(e) => Some(Box::new(e)),
+        }
+    }
+}
+
+impl<T: Persistent + Expiring> FileQueue<T> {
+    /// Recovers the queue from `directory`. This reads and may truncate every
+    /// segment, so it shouldn't be called on the runtime's own threads.
+    pub fn open(directory: &Path, settings: &StorageSettings) -> Result<Self, WebMQError> {
+        let expiry = |record: &StoredRecord| decode::<T>(record).ok().and_then(|item| item.expires_at());
+        Ok(FileQueue {
+            log: Arc::new(Mutex::new(SegmentLog::open(directory, settings, &expiry)?)),
+            _data: PhantomData,
+        })
+    }
+}
+
+impl<T> FileQueue<T> {
+    /// Runs `operation` on the log on the blocking thread pool, so disk access
+    /// never stalls the runtime. Once started, the operation runs to completion
+    /// even if the caller stops waiting for it.
+    async fn blocking<R: Send + 'static>(
+        &self,
+        operation: impl FnOnce(&mut SegmentLog) -> Result<R, WebMQError> + Send + 'static,
+    ) -> Result<R, WebMQError> {
+        let log = self.log.clone();
+        let res = tokio::task::spawn_blocking(move || match log.lock() {
+            Ok(mut log) => operation(&mut log),
+            Err(_) => Err(WebMQError::Unrecoverable),
+        });
+
+        match res.await {
+            Ok(res) => res,
+            Err(e) => Err(WebMQError::Data(format!("Storage task failed: {e}"))),
+        }
+    }
+}
+
+impl SegmentLog {
+    fn open(
+        directory: &Path,
+        settings: &StorageSettings,
+        expiry: &dyn Fn(&StoredRecord) -> Option<u64>,
+    ) -> Result<Self, WebMQError> {
+        if let Err(e) = fs::create_dir_all(directory) {
+            return Err(file_error(directory, "create queue directory", e));
+        }
+
+        let segments = list_segments(directory)?;
+        let (head_segment, head_offset) = match read_head(directory)? {
+            Some(head) => head,
+            None => (segments.first().copied().unwrap_or(0), 0),
+        };
+
+        let mut records = VecDeque::new();
+        for &segment in segments.iter() {
+            let path = segment_path(directory, segment);
+            if segment < head_segment {
+                remove_segment(&path);
+                continue;
+            }
+
+            let start = if segment == head_segment {
+                head_offset
+            } else {
+                0
+            };
+            recover_segment(&path, segment, start, expiry, &mut records)?;
+        }
+
+        let active_id = segments
+            .last()
+            .copied()
+            .unwrap_or(head_segment)
+            .max(head_segment);
+        let writer = open_active_segment(directory, active_id)?;
+
+        // Normalize the head so it always points at the first recovered record,
+        // even if the previous head was ahead of what actually reached the disk.
+        let (segment, offset) = match records.front() {
+            Some(first) => (first.segment, first.offset),
+            None => (writer.id, writer.length),
+        };
+        write_head(
+            directory,
+            segment,
+            offset,
+            settings.fsync != FsyncPolicy::Never,
+        )?;
+
+        info!(
+            "Recovered {} message(s) from {}",
+            records.len(),
+            directory.to_string_lossy()
+        );
+
+        Ok(SegmentLog {
+            directory: directory.to_path_buf(),
+            segment_size: settings.segment_size,
+            fsync: settings.fsync,
+            fsync_batch_size: settings.fsync_batch_size.max(1),
+            unsynced_writes: 0,
+            writer,
+            records,
+            reserved: HashMap::new(),
+            held: BTreeSet::new(),
+            next_token: 0,
+            head: (segment, offset),
+        })
+    }
+
+    /// Reserves and releases in one go, so the head moves with the pop.
+    fn pop_many<T: Persistent + ByteSize>(
+        &mut self,
+        max: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<T>, WebMQError> {
+        let reserved = self.reserve_many(max, max_bytes)?;
+        let tokens: Vec<u64> = reserved.iter().map(|item| item.token).collect();
+        let positions: Vec<RecordPosition> = tokens.iter().map(|token| self.reserved[token]).collect();
+        if let Err(e) = self.release(&tokens) {
+            for position in positions.into_iter().rev() {
+                self.records.push_front(position);
+            }
+            return Err(e);
+        }
+
+        Ok(reserved.into_iter().map(|item| item.data).collect())
+    }
+
+    /// Takes records out of the visible part of the log without moving the
+    /// head. Corrupted records on the way are set aside and dropped for good.
+    fn reserve_many<T: Persistent + ByteSize>(
+        &mut self,
+        max: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Reserved<T>>, WebMQError> {
+        let mut items = vec![];
+        let mut readable = vec![];
+        let mut consumed = 0;
+        let mut bytes = 0;
+        while items.is_empty() || items.len() < max {
+            let Some(position) = self.records.get(consumed).copied() else {
+                break;
+            };
+
+            let data: T = match self.read_record(&position) {
+                Ok(data) => data,
+                Err(WebMQError::Data(e)) => {
+                    self.set_aside(&position, &e);
+                    consumed += 1;
+                    continue;
+                }
+                Err(e) if items.is_empty() && consumed == 0 => return Err(e),
+                Err(_) => break,
+            };
+            let size = data.byte_size();
+            if !items.is_empty() && bytes + size > max_bytes {
+                break;
+            }
+
+            bytes += size;
+            items.push(data);
+            readable.push(consumed);
+            consumed += 1;
+        }
+
+        let skipped = consumed > items.len();
+        let taken: Vec<RecordPosition> = self.records.drain(..consumed).collect();
+        let mut reserved = Vec::with_capacity(items.len());
+        for (index, data) in readable.into_iter().zip(items) {
+            let token = self.hold(taken[index]);
+            reserved.push(Reserved { token, data });
+        }
+
+        // Only dropped records can let the head move here, and failing to
+        // persist that just means they are skipped again after a restart.
+        if skipped && let Err(e) = self.update_head() {
+            warn!("{e}");
+        }
+
+        if reserved.is_empty() {
+            return Err(WebMQError::Data(
+                "Failed to pop data from queue as it contains no elements".into(),
+            ));
+        }
+
+        Ok(reserved)
+    }
+
+    /// Takes every expired record out of the visible part of the log, like
+    /// `reserve_many`. Records which can't be read for now are left in place.
+    fn reserve_expired<T: Persistent>(&mut self, now: u64) -> Result<Vec<Reserved<T>>, WebMQError> {
+        let mut reserved = vec![];
+        let mut skipped = false;
+        let mut records = VecDeque::with_capacity(self.records.len());
+        for position in std::mem::take(&mut self.records) {
+            if position.expires_at.is_none_or(|expires_at| expires_at > now) {
+                records.push_back(position);
+                continue;
+            }
+
+            match self.read_record(&position) {
+                Ok(data) => reserved.push(Reserved {
+                    token: self.hold(position),
+                    data,
+                }),
+                Err(WebMQError::Data(e)) => {
+                    self.set_aside(&position, &e);
+                    skipped = true;
+                }
+                Err(e) => {
+                    warn!("Could not read expired record: {e}");
+                    records.push_back(position);
+                }
+            }
+        }
+        self.records = records;
+
+        if skipped && let Err(e) = self.update_head() {
+            warn!("{e}");
+        }
+
+        Ok(reserved)
+    }
+
+    fn hold(&mut self, position: RecordPosition) -> u64 {
+        let token = self.next_token;
+        self.next_token += 1;
+        self.reserved.insert(token, position);
+        self.held.insert((position.segment, position.offset));
+        token
+    }
+
+    /// Drops reserved records for good. Unknown tokens are ignored.
+    fn release(&mut self, tokens: &[u64]) -> Result<(), WebMQError> {
+        for token in tokens {
+            if let Some(position) = self.reserved.remove(token) {
+                self.held.remove(&(position.segment, position.offset));
+            }
+        }
+
+        self.update_head()
+    }
+
+    /// Reads records without consuming them, leaving out corrupted ones.
+    fn browse<T: Persistent>(&self, offset: usize, limit: usize) -> Result<Vec<T>, WebMQError> {
+        let mut items = vec![];
+        for position in self.records.iter().skip(offset) {
+            if items.len() >= limit {
+                break;
+            }
+
+            match self.read_record(position) {
+                Ok(data) => items.push(data),
+                Err(WebMQError::Data(_)) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok(items)
+    }
+
+    fn read_record<T: Persistent>(&self, position: &RecordPosition) -> Result<T, WebMQError> {
+        let path = segment_path(&self.directory, position.segment);
+        let mut file = match File::open(&path) {
+            Ok(f) => f,
+            Err(e) => return Err(file_error(&path, "open segment", e)),
+        };
+
+        let record = match read_record_at(&mut file, position.offset) {
+            Ok(Some(record)) => record,
+            Ok(None) => {
+                return Err(WebMQError::Data(format!(
+                    "Record at offset {} of {} is corrupted",
+                    position.offset,
+                    path.to_string_lossy()
+                )));
+            }
+            Err(e) => return Err(file_error(&path, "read segment", e)),
+        };
+
+        decode(&record)
+    }
+
+    /// Copies a record which can't be read to the `corrupt` file, so it can be
+    /// skipped without losing what is left of it.
+    fn set_aside(&self, position: &RecordPosition, reason: &str) {
+        warn!("Skipping unreadable record: {reason}");
+
+        let path = segment_path(&self.directory, position.segment);
+        let corrupt_path = self.directory.join(CORRUPT_FILE);
+        let res = File::open(&path).and_then(|mut file| {
+            let mut record = vec![0u8; (position.end() - position.offset) as usize];
+            file.seek(SeekFrom::Start(position.offset))?;
+            file.read_exact(&mut record)?;
+
+            let mut corrupt = OpenOptions::new().create(true).append(true).open(&corrupt_path)?;
+            corrupt.write_all(&record)
+        });
+        if let Err(e) = res {
+            warn!(
+                "Could not copy unreadable record to {}: {e}",
+                corrupt_path.to_string_lossy()
+            );
+        }
+    }
+
+    /// Appends all of `data` to the same segment with a single write, so
+    /// either every record is added or none of them are. A crash in the middle
+    /// of the write may still leave the records before the tear behind.
+    fn append_records<T: Persistent + Expiring>(&mut self, data: &[T]) -> Result<Vec<RecordPosition>, WebMQError> {
+        let mut records = vec![];
+        let mut lengths = Vec::with_capacity(data.len());
+        for item in data {
+            let mut payload = vec![T::FORMAT_VERSION];
+            match item.encode() {
+                Ok(encoded) => payload.extend(encoded),
+                Err(e) => return Err(WebMQError::Data(format!("Could not encode message: {e}"))),
+            };
+            let length = match u32::try_from(payload.len()) {
+                Ok(length) if length & TAGGED_RECORD == 0 => length,
+                _ => return Err(WebMQError::Data("Message is too large to be stored".into())),
+            };
+
+            records.extend_from_slice(&(length | TAGGED_RECORD).to_le_bytes());
+            records.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+            records.extend_from_slice(&payload);
+            lengths.push((length, item.expires_at()));
+        }
+
+        let batch_size = records.len() as u64;
+        if self.writer.length > 0 && self.writer.length + batch_size > self.segment_size {
+            self.rotate_segment()?;
+        }
+
+        let path = segment_path(&self.directory, self.writer.id);
+        if let Err(e) = self.writer.file.write_all(&records) {
+            // Drop whatever part of the records made it to disk so the next
+            // append starts on a record boundary again.
+            let _ = self.writer.file.set_len(self.writer.length);
+            return Err(file_error(&path, "append to segment", e));
+        }
+
+        let mut positions = Vec::with_capacity(lengths.len());
+        for (length, expires_at) in lengths {
+            let position = RecordPosition {
+                segment: self.writer.id,
+                offset: self.writer.length,
+                length,
+                expires_at,
+            };
+            self.writer.length = position.end();
+            positions.push(position);
+        }
+        self.sync_writer(positions.len())?;
+
+        Ok(positions)
+    }
+
+    fn rotate_segment(&mut self) -> Result<(), WebMQError> {
+        if self.fsync != FsyncPolicy::Never {
+            let _ = self.writer.file.sync_data();
+        }
+        self.unsynced_writes = 0;
+        self.writer = open_active_segment(&self.directory, self.writer.id + 1)?;
+        Ok(())
+    }
+
+    fn sync_writer(&mut self, writes: usize) -> Result<(), WebMQError> {
+        let sync = match self.fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Batch => {
+                self.unsynced_writes += writes;
+                self.unsynced_writes >= self.fsync_batch_size
+            }
+            FsyncPolicy::Never => false,
+        };
+
+        if !sync {
+            return Ok(());
+        }
+
+        self.unsynced_writes = 0;
+        match self.writer.file.sync_data() {
+            Ok(_) => Ok(()),
+            Err(e) => Err(file_error(
+                &segment_path(&self.directory, self.writer.id),
+                "sync segment",
+                e,
+            )),
+        }
+    }
+
+    /// Persists the position of the oldest record still needed as the head
+    /// and removes the segments it has moved past.
+    fn update_head(&mut self) -> Result<(), WebMQError> {
+        let oldest_visible = self.records.front().map(|first| (first.segment, first.offset));
+        let (segment, offset) = self
+            .held
+            .first()
+            .copied()
+            .into_iter()
+            .chain(oldest_visible)
+            .min()
+            .unwrap_or((self.writer.id, self.writer.length));
+        if (segment, offset) == self.head {
+            return Ok(());
+        }
+
+        write_head(
+            &self.directory,
+            segment,
+            offset,
+            self.fsync == FsyncPolicy::Always,
+        )?;
+        let (first, _) = std::mem::replace(&mut self.head, (segment, offset));
+
+        for old in first..segment {
+            if old != self.writer.id {
+                remove_segment(&segment_path(&self.directory, old));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Maps a queue name onto a directory name that is safe to create under the
+/// storage root, regardless of what the client put in the URL. An empty name
+/// would map onto the root itself, so it is refused.
+pub fn queue_directory(root: &Path, queue: &str) -> Result<PathBuf, WebMQError> {
+    if queue.is_empty() {
+        return Err(WebMQError::Data("Queue names can't be empty".into()));
+    }
+
+    let mut name = String::with_capacity(queue.len());
+    for byte in queue.bytes() {
+        if byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'-' {
+            name.push(byte as char);
+        } else {
+            name.push_str(&format!("%{byte:02X}"));
+        }
+    }
+
+    Ok(root.join(name))
+}
+
+/// Lists the names of all queues which have a directory under `root`.
+pub fn discover_queues(root: &Path) -> Result<Vec<String>, WebMQError> {
+    let entries = match fs::read_dir(root) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(file_error(root, "read storage directory", e)),
+    };
+
+    let mut queues = vec![];
+    for entry in entries.flatten() {
+        if !entry.path().is_dir() {
+            continue;
+        }
+
+        let name = entry.file_name();
+        match decode_queue_name(&name.to_string_lossy()) {
+            Some(queue) => queues.push(queue),
+            None => warn!(
+                "Ignoring unrecognized directory {} in storage",
+                entry.path().to_string_lossy()
+            ),
+        }
+    }
+
+    Ok(queues)
+}
+
+fn decode_queue_name(name: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut chars = name.bytes();
+    while let Some(byte) = chars.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+
+        let high = (chars.next()? as char).to_digit(16)?;
+        let low = (chars.next()? as char).to_digit(16)?;
+        bytes.push((high * 16 + low) as u8);
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+fn segment_path(directory: &Path, segment: u64) -> PathBuf {
+    directory.join(format!("{segment:020}.{SEGMENT_EXTENSION}"))
+}
+
+fn list_segments(directory: &Path) -> Result<Vec<u64>, WebMQError> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(e) => return Err(file_error(directory, "read queue directory", e)),
+    };
+
+    let mut segments: Vec<u64> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let path = entry.path();
+            if path.extension()? != SEGMENT_EXTENSION {
+                return None;
+            }
+            path.file_stem()?.to_str()?.parse().ok()
+        })
+        .collect();
+    segments.sort_unstable();
+
+    Ok(segments)
+}
+
+fn open_active_segment(directory: &Path, id: u64) -> Result<ActiveSegment, WebMQError> {
+    let path = segment_path(directory, id);
+    let file = match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(f) => f,
+        Err(e) => return Err(file_error(&path, "open segment", e)),
+    };
+
+    let length = match file.metadata() {
+        Ok(meta) => meta.len(),
+        Err(e) => return Err(file_error(&path, "inspect segment", e)),
+    };
+
+    Ok(ActiveSegment { id, file, length })
+}
+
+/// Indexes every intact record in a segment starting at `start` and truncates
+/// the segment after the last one. `expiry` tells when a record expires.
+fn recover_segment(
+    path: &Path,
+    segment: u64,
+    start: u64,
+    expiry: &dyn Fn(&StoredRecord) -> Option<u64>,
+    records: &mut VecDeque<RecordPosition>,
+) -> Result<(), WebMQError> {
+    let mut file = match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(f) => f,
+        Err(e) => return Err(file_error(path, "open segment", e)),
+    };
+
+    let file_length = match file.metadata() {
+        Ok(meta) => meta.len(),
+        Err(e) => return Err(file_error(path, "inspect segment", e)),
+    };
+
+    let mut offset = start.min(file_length);
+    while offset < file_length {
+        match read_record_at(&mut file, offset) {
+            Ok(Some(record)) => {
+                let position = RecordPosition {
+                    segment,
+                    offset,
+                    length: record.length,
+                    expires_at: expiry(&record),
+                };
+                offset = position.end();
+                records.push_back(position);
+            }
+            Ok(None) => break,
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(file_error(path, "read segment", e)),
+        }
+    }
+
+    if offset < file_length {
+        warn!(
+            "Truncating {} bytes of incomplete or corrupted data from {}",
+            file_length - offset,
+            path.to_string_lossy()
+        );
+        if let Err(e) = file.set_len(offset) {
+            return Err(file_error(path, "truncate segment", e));
+        }
+    }
+
+    Ok(())
+}
+
+fn decode<T: Persistent>(record: &StoredRecord) -> Result<T, WebMQError> {
+    T::decode(record.version, &record.payload)
+        .map_err(|e| WebMQError::Data(format!("Could not decode stored message: {e}")))
+}
+
+/// Reads the record at `offset`, returning `None` if its checksum doesn't
+/// match the payload.
+fn read_record_at(file: &mut File, offset: u64) -> std::io::Result<Option<StoredRecord>> {
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+    file.read_exact(&mut header)?;
+    let field = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    let checksum = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+    let length = field & !TAGGED_RECORD;
+
+    let mut payload = vec![0u8; length as usize];
+    file.read_exact(&mut payload)?;
+
+    if crc32fast::hash(&payload) != checksum {
+        return Ok(None);
+    }
+
+    if field & TAGGED_RECORD == 0 {
+        return Ok(Some(StoredRecord {
+            version: None,
+            payload,
+            length,
+        }));
+    }
+
+    let Some(&version) = payload.first() else {
+        return Ok(None);
+    };
+    payload.remove(0);
+    Ok(Some(StoredRecord {
+        version: Some(version),
+        payload,
+        length,
+    }))
+}
+
+fn read_head(directory: &Path) -> Result<Option<(u64, u64)>, WebMQError> {
+    let path = directory.join(HEAD_FILE);
+    let buffer = match fs::read(&path) {
+        Ok(b) => b,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(file_error(&path, "read head", e)),
+    };
+
+    let Ok(buffer) = <[u8; 16]>::try_from(buffer.as_slice()) else {
+        warn!("Ignoring malformed head file {}", path.to_string_lossy());
+        return Ok(None);
+    };
+
+    let (segment, offset) = buffer.split_at(8);
+    Ok(Some((
+        u64::from_le_bytes(segment.try_into().unwrap()),
+        u64::from_le_bytes(offset.try_into().unwrap()),
+    )))
+}
+
+fn write_head(directory: &Path, segment: u64, offset: u64, sync: bool) -> Result<(), WebMQError> {
+    let temp_path = directory.join(HEAD_TEMP_FILE);
+    let mut buffer = [0u8; 16];
+    buffer[..8].copy_from_slice(&segment.to_le_bytes());
+    buffer[8..].copy_from_slice(&offset.to_le_bytes());
+
+    let result = File::create(&temp_path).and_then(|mut file| {
+        file.write_all(&buffer)?;
+        if sync {
+            file.sync_data()?;
+        }
+        Ok(())
+    });
+    if let Err(e) = result {
+        return Err(file_error(&temp_path, "write head", e));
+    }
+
+    let path = directory.join(HEAD_FILE);
+    match fs::rename(&temp_path, &path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(file_error(&path, "replace head", e)),
+    }
+}
+
+fn remove_segment(path: &Path) {
+    if let Err(e) = fs::remove_file(path) {
+        warn!(
+            "Could not remove consumed segment {}: {e}",
+            path.to_string_lossy()
+        );
+    }
+}
+
+fn file_error(path: &Path, action: &str, error: std::io::Error) -> WebMQError {
+    WebMQError::File(format!(
+        "Couldn't {action} {}: {error}",
+        path.to_string_lossy()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::core::models::message::{DeliveryFailure, Message, unix_millis};
+
+    fn temp_directory() -> PathBuf {
+        std::env::temp_dir().join(format!("webmq-test-{}", Uuid::new_v4().simple()))
+    }
+
+    async fn filled_queue(directory: &Path, settings: &StorageSettings, count: u8) {
+        let mut queue: FileQueue<Message> = FileQueue::open(directory, settings).unwrap();
+        for i in 0..count {
+            assert!(queue.push(Message::new(vec![i; 16])).await.is_none());
+        }
+    }
+
+    async fn drain(queue: &mut FileQueue<Message>) -> Vec<Vec<u8>> {
+        let mut data = vec![];
+        while let Ok(message) = queue.pop().await {
+            data.push(message.data);
+        }
+        data
+    }
+
+    fn only_segment(directory: &Path) -> PathBuf {
+        let segments = list_segments(directory).unwrap();
+        assert_eq!(segments.len(), 1);
+        segment_path(directory, segments[0])
+    }
+
+    #[tokio::test]
+    async fn recovers_intact_records_before_truncated_tail() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        filled_queue(&directory, &settings, 3).await;
+
+        let segment = only_segment(&directory);
+        let length = fs::metadata(&segment).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&segment)
+            .unwrap()
+            .set_len(length - 5)
+            .unwrap();
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(drain(&mut queue).await, vec![vec![0; 16], vec![1; 16]]);
+
+        // The torn record is gone, so new records are appended after the
+        // intact ones.
+        assert!(queue.push(Message::new(vec![9; 16])).await.is_none());
+        drop(queue);
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(drain(&mut queue).await, vec![vec![9; 16]]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn recovers_intact_records_before_corrupted_tail() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        filled_queue(&directory, &settings, 3).await;
+
+        let segment = only_segment(&directory);
+        let mut bytes = fs::read(&segment).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xFF;
+        fs::write(&segment, bytes).unwrap();
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(queue.stats().messages, 2);
+        assert_eq!(drain(&mut queue).await, vec![vec![0; 16], vec![1; 16]]);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn skips_records_corrupted_after_recovery() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        for i in 0..3 {
+            assert!(queue.push(Message::new(vec![i; 16])).await.is_none());
+        }
+
+        // Flip the last byte of the first record.
+        let first = queue.log.lock().unwrap().records[0];
+        let segment = segment_path(&directory, first.segment);
+        let mut bytes = fs::read(&segment).unwrap();
+        bytes[first.end() as usize - 1] ^= 0xFF;
+        fs::write(&segment, bytes).unwrap();
+
+        assert_eq!(drain(&mut queue).await, vec![vec![1; 16], vec![2; 16]]);
+        assert!(directory.join(CORRUPT_FILE).exists());
+
+        drop(queue);
+        let queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(queue.stats().messages, 0);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resumes_from_head_across_segments() {
+        let directory = temp_directory();
+        let settings = StorageSettings {
+            segment_size: 64,
+            ..StorageSettings::default()
+        };
+        filled_queue(&directory, &settings, 6).await;
+        assert!(list_segments(&directory).unwrap().len() > 1);
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        for i in 0..4 {
+            assert_eq!(queue.pop().await.unwrap().data, vec![i; 16]);
+        }
+        let segments = list_segments(&directory).unwrap();
+        drop(queue);
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(list_segments(&directory).unwrap(), segments);
+        assert_eq!(drain(&mut queue).await, vec![vec![4; 16], vec![5; 16]]);
+        assert_eq!(list_segments(&directory).unwrap().len(), 1);
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn redelivers_unreleased_records_after_reopening() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        filled_queue(&directory, &settings, 3).await;
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        let reserved = queue.reserve_many(2, usize::MAX).await.unwrap();
+        assert_eq!(queue.stats().messages, 1);
+
+        // Releasing the second record can't move the head past the first.
+        assert!(queue.release(vec![reserved[1].token]).await.is_none());
+        drop(queue);
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert_eq!(drain(&mut queue).await, vec![vec![0; 16], vec![1; 16], vec![2; 16]]);
+        drop(queue);
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert!(drain(&mut queue).await.is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn finds_expired_records_behind_live_ones() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        let expired = Message {
+            expires_at: Some(1),
+            ..Message::new(vec![1; 16])
+        };
+        let messages = vec![Message::new(vec![0; 16]), expired, Message::new(vec![2; 16])];
+        assert!(queue.push_many(messages).await.is_none());
+        drop(queue);
+
+        // Expiry times are picked up again when the log is recovered.
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        let reserved = queue.reserve_expired(unix_millis()).await.unwrap();
+        assert_eq!(reserved.len(), 1);
+        assert_eq!(reserved[0].data.data, vec![1; 16]);
+        assert!(queue.release(vec![reserved[0].token]).await.is_none());
+        assert_eq!(drain(&mut queue).await, vec![vec![0; 16], vec![2; 16]]);
+        drop(queue);
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert!(drain(&mut queue).await.is_empty());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reads_records_written_before_they_were_tagged() {
+        let directory = temp_directory();
+        let settings = StorageSettings::default();
+        fs::create_dir_all(&directory).unwrap();
+
+        let failures = vec![DeliveryFailure::now("Rejected by consumer".to_string())];
+        let mut current = Message::new(vec![3; 16]);
+        current.expires_at = Some(u64::MAX);
+        let payloads = [
+            bincode::serialize(&vec![0u8; 16]).unwrap(),
+            bincode::serialize(&(vec![1u8; 16], 1u32, &failures, Some("source"))).unwrap(),
+            bincode::serialize(&(
+                "id",
+                7u64,
+                Some("text/plain"),
+                BTreeMap::from([("key", "value")]),
+                vec![2u8; 16],
+                2u32,
+                &failures,
+                None::<String>,
+            ))
+            .unwrap(),
+            bincode::serialize(&current).unwrap(),
+        ];
+        let mut segment = vec![];
+        for payload in payloads {
+            segment.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+            segment.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+            segment.extend_from_slice(&payload);
+        }
+        fs::write(segment_path(&directory, 0), segment).unwrap();
+
+        // New records go right behind the old ones.
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        assert!(queue.push(Message::new(vec![4; 16])).await.is_none());
+        drop(queue);
+
+        let mut queue: FileQueue<Message> = FileQueue::open(&directory, &settings).unwrap();
+        let messages = queue.pop_many(5, usize::MAX).await.unwrap();
+        let data: Vec<Vec<u8>> = messages.iter().map(|message| message.data.clone()).collect();
+        assert_eq!(data, (0..5).map(|i| vec![i; 16]).collect::<Vec<_>>());
+        assert_eq!(messages[1].failures.len(), 1);
+        assert_eq!(messages[1].dead_letter_source.as_deref(), Some("source"));
+        assert_eq!(messages[2].id, "id");
+        assert_eq!(messages[2].headers["key"], "value");
+        assert_eq!(messages[2].expires_at, None);
+        assert_eq!(messages[3].expires_at, Some(u64::MAX));
+        assert!(!directory.join(CORRUPT_FILE).exists());
+
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn never_maps_a_queue_onto_the_root() {
+        let root = Path::new("/data");
+        assert!(queue_directory(root, "").is_err());
+        assert_eq!(queue_directory(root, "a/..").unwrap(), root.join("a%2F%2E%2E"));
+    }
+}
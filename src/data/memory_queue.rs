@@ -2,14 +2,15 @@ use std::{collections::LinkedList, error::Error};
 
 use async_trait::async_trait;
 
-use crate::core::{errors::WebMQError, traits::AsyncQueue};
+use crate::core::{errors::WebMQError, models::{delivery::Reserved, queue_info::QueueStats}, traits::{AsyncQueue, ByteSize, Expiring}};
 
 pub struct MemoryQueue<T> {
-    queue: LinkedList<T>
+    queue: LinkedList<T>,
+    next_token: u64,
 }
 
 #[async_trait]
-impl<T: Send + Sync> AsyncQueue<T> for MemoryQueue<T> {
+impl<T: ByteSize + Expiring + Clone + Send + Sync> AsyncQueue<T> for MemoryQueue<T> {
     async fn pop(&mut self) -> Result<T, Box<dyn Error>> {
         let Some(data) = self.queue.pop_front() else {
             return Err(Box::new(WebMQError::Data("Failed to pop data from queue as it contains no elements".into())))
@@ -18,16 +19,105 @@ impl<T: Send + Sync> AsyncQueue<T> for MemoryQueue<T> {
         Ok(data)
     }
 
+    async fn pop_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<T>, Box<dyn Error>> {
+        let mut items = vec![self.pop().await?];
+        let mut bytes = items[0].byte_size();
+        while items.len() < max {
+            match self.queue.front() {
+                Some(next) if bytes + next.byte_size() <= max_bytes => bytes += next.byte_size(),
+                _ => break,
+            }
+            items.extend(self.queue.pop_front());
+        }
+
+        Ok(items)
+    }
+
+    /// Nothing survives the process anyway, so reserving is the same as
+    /// popping and there is nothing to release. Tokens still tell the
+    /// reservations apart.
+    async fn reserve_many(&mut self, max: usize, max_bytes: usize) -> Result<Vec<Reserved<T>>, Box<dyn Error>> {
+        let items = self.pop_many(max, max_bytes).await?;
+        Ok(items
+            .into_iter()
+            .map(|data| {
+                self.next_token += 1;
+                Reserved { token: self.next_token, data }
+            })
+            .collect())
+    }
+
+    async fn release(&mut self, _tokens: Vec<u64>) -> Option<Box<dyn Error>> {
+        None
+    }
+
+    async fn reserve_expired(&mut self, now: u64) -> Result<Vec<Reserved<T>>, Box<dyn Error>> {
+        let (expired, live): (LinkedList<T>, LinkedList<T>) = std::mem::take(&mut self.queue)
+            .into_iter()
+            .partition(|item| item.expires_at().is_some_and(|expires_at| expires_at <= now));
+        self.queue = live;
+
+        Ok(expired
+            .into_iter()
+            .map(|data| {
+                self.next_token += 1;
+                Reserved { token: self.next_token, data }
+            })
+            .collect())
+    }
+
+    async fn peek(&self) -> Result<T, Box<dyn Error>> {
+        let Some(data) = self.queue.front() else {
+            return Err(Box::new(WebMQError::Data("Failed to peek at queue as it contains no elements".into())))
+        };
+
+        Ok(data.clone())
+    }
+
+    async fn browse(&self, offset: usize, limit: usize) -> Result<Vec<T>, Box<dyn Error>> {
+        Ok(self.queue.iter().skip(offset).take(limit).cloned().collect())
+    }
+
     async fn push(&mut self, data: T) -> Option<Box<dyn Error>> {
         self.queue.push_back(data);
         None
     }
+
+    async fn push_many(&mut self, data: Vec<T>) -> Option<Box<dyn Error>> {
+        self.queue.extend(data);
+        None
+    }
+
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            messages: self.queue.len(),
+            bytes: self.queue.iter().map(ByteSize::byte_size).sum(),
+        }
+    }
+
+    async fn purge(&mut self) -> Result<usize, Box<dyn Error>> {
+        let count = self.queue.len();
+        self.queue.clear();
+        Ok(count)
+    }
+
+    async fn delete(&mut self) -> Option<Box<dyn Error>> {
+        self.queue.clear();
+        None
+    }
+}
+
+impl<T> Default for MemoryQueue<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> MemoryQueue<T> {
     pub fn new() -> MemoryQueue<T> {
         MemoryQueue {
-            queue: LinkedList::new()
+            queue: LinkedList::new(),
+            next_token: 0,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,92 @@
+use std::collections::BTreeMap;
+
+use bincode::Options;
+use serde::Deserialize;
+
+use crate::core::{
+    models::message::{DeliveryFailure, Message},
+    traits::Persistent,
+};
+
+/// A message as stored before `expires_at` was added. Records weren't tagged
+/// with a format yet, and neither were those of the older layouts below.
+#[derive(Deserialize)]
+struct UntaggedWithMetadata {
+    id: String,
+    timestamp: u64,
+    content_type: Option<String>,
+    headers: BTreeMap<String, String>,
+    data: Vec<u8>,
+    delivery_count: u32,
+    failures: Vec<DeliveryFailure>,
+    dead_letter_source: Option<String>,
+}
+
+/// A message as stored before it had an id, timestamp or headers.
+#[derive(Deserialize)]
+struct UntaggedWithFailures {
+    data: Vec<u8>,
+    delivery_count: u32,
+    failures: Vec<DeliveryFailure>,
+    dead_letter_source: Option<String>,
+}
+
+impl Persistent for Message {
+    const FORMAT_VERSION: u8 = 1;
+
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        bincode::serialize(self).map_err(|e| e.to_string())
+    }
+
+    fn decode(version: Option<u8>, payload: &[u8]) -> Result<Self, String> {
+        match version {
+            Some(Self::FORMAT_VERSION) => strict().deserialize(payload).map_err(|e| e.to_string()),
+            Some(version) => Err(format!("Unknown message format {version}")),
+            None => decode_untagged(payload),
+        }
+    }
+}
+
+/// Tries every layout untagged records were written in, newest first. With
+/// trailing bytes refused, a record doesn't fit any layout newer than the one
+/// it was written in.
+fn decode_untagged(payload: &[u8]) -> Result<Message, String> {
+    if let Ok(message) = strict().deserialize::<Message>(payload) {
+        return Ok(message);
+    }
+
+    if let Ok(old) = strict().deserialize::<UntaggedWithMetadata>(payload) {
+        return Ok(Message {
+            id: old.id,
+            timestamp: old.timestamp,
+            content_type: old.content_type,
+            headers: old.headers,
+            delivery_count: old.delivery_count,
+            failures: old.failures,
+            dead_letter_source: old.dead_letter_source,
+            ..Message::new(old.data)
+        });
+    }
+
+    if let Ok(old) = strict().deserialize::<UntaggedWithFailures>(payload) {
+        return Ok(Message {
+            delivery_count: old.delivery_count,
+            failures: old.failures,
+            dead_letter_source: old.dead_letter_source,
+            ..Message::new(old.data)
+        });
+    }
+
+    // The very first records held nothing but the message body.
+    match strict().deserialize::<Vec<u8>>(payload) {
+        Ok(data) => Ok(Message::new(data)),
+        Err(e) => Err(format!("Unrecognized message layout: {e}")),
+    }
+}
+
+/// The options `bincode::serialize` writes with, refusing leftover bytes.
+fn strict() -> impl Options {
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+}
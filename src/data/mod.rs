@@ -1 +1,3 @@
-pub mod memory_queue;
\ No newline at end of file
+pub mod file_queue;
+pub mod memory_queue;
+pub mod message_format;
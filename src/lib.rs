@@ -0,0 +1,7 @@
+pub mod core;
+pub mod network;
+pub mod utils;
+pub mod adapter;
+pub mod auth;
+pub mod messaging;
+pub mod data;
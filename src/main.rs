@@ -1,23 +1,27 @@
-use core::errors::WebMQError;
-use core::traits::{AsyncQueue, AsyncStart};
-use core::config::main::Settings;
+use webmq::core::errors::WebMQError;
+use webmq::core::models::message::Message;
+use webmq::core::traits::AsyncStart;
+use webmq::core::config::http2::Http2Settings;
+use webmq::core::config::main::Settings;
+use webmq::core::config::network::ListenerSettings;
+use webmq::core::config::storage::{StorageBackend, StorageSettings};
+use std::path::Path;
 use std::sync::Arc;
-use std::{error::Error, net::Ipv4Addr, str::FromStr};
+use std::{error::Error, net::SocketAddr};
 
-use adapter::hyper_adapter::HyperAdapter;
-use data::memory_queue::MemoryQueue;
-use log::{debug, error};
-use messaging::base_dispatcher::BaseMessagingDispatcher;
-use network::listener::hyper::https::HttpsListener;
+use webmq::adapter::hyper_adapter::HyperAdapter;
+use webmq::adapter::limits::MessageLimits;
+use webmq::auth::authenticator::Authenticator;
+use webmq::auth::policy::Policy;
+use webmq::data::file_queue::{FileQueue, discover_queues, queue_directory};
+use webmq::data::memory_queue::MemoryQueue;
+use futures_util::future::{BoxFuture, join_all};
+use log::{debug, error, info};
+use webmq::messaging::base_dispatcher::{BaseMessagingDispatcher, BoxedQueue, QueueFac};
+use webmq::network::listener::hyper::http::HttpListener;
+use webmq::network::listener::hyper::https::HttpsListener;
 use tls_listener::rustls::rustls;
-use tokio::sync::Mutex;
-
-pub mod core;
-pub mod network;
-pub mod utils;
-pub mod adapter;
-pub mod messaging;
-pub mod data;
+use tokio::net::lookup_host;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
@@ -28,37 +32,157 @@ async fn main() -> Result<(), Box<dyn Error + Send + Sync>> {
         .expect("Failed to install rustls cryptography provider");
     debug!("Installed rustls cryptography provider.");
 
-    let config = Settings::load();
+    let config = match Settings::load() {
+        Ok(config) => config,
+        Err(e) => {
+            error!("{e}");
+            return Err(WebMQError::Unrecoverable.into());
+        }
+    };
 
-    let Ok(ip) = Ipv4Addr::from_str(config.network.ip.as_str()) else {
-        error!("Couldn't parse IP adress");
+    if let Some(e) = config.queues.iter().find_map(|(queue, settings)| settings.validate(queue).err()) {
+        error!("{e}");
         return Err(WebMQError::Unrecoverable.into());
-    };
+    }
+
+    let limits = MessageLimits::new(
+        config.messaging.max_message_size,
+        config.messaging.max_batch_size,
+        &config.queues,
+    );
+    let mut dispatcher = BaseMessagingDispatcher::new(
+        create_queue_factory(config.storage.clone()),
+        config.queues,
+    );
+    dispatcher.restore_subscriptions(config.topics);
+    if config.storage.backend == StorageBackend::File {
+        let restored = match discover_queues(Path::new(config.storage.directory.as_str())) {
+            Ok(queues) => Ok(dispatcher.restore(queues).await),
+            Err(e) => Err(e),
+        };
+        match restored {
+            Ok(None) => info!("Restored queues from {}", config.storage.directory),
+            Ok(Some(e)) | Err(e) => {
+                error!("Couldn't restore queues from storage: {e}");
+                return Err(WebMQError::Unrecoverable.into());
+            }
+        }
+    }
 
-    let dispatcher = Box::new(BaseMessagingDispatcher::new(Box::pin(create_memory_queue)));
-    let adapter = HyperAdapter {
-        dispatcher: Mutex::new(dispatcher)
+    let authenticator = match Authenticator::load(&config.auth) {
+        Ok(authenticator) => authenticator,
+        Err(e) => {
+            error!("Couldn't load authentication keys: {e}");
+            return Err(WebMQError::Unrecoverable.into());
+        }
     };
-    let listener: Box<dyn AsyncStart> = match HttpsListener::new(
-        ip,
-        config.network.port,
-        config.network.tls,
-        Arc::new(adapter),
-    )
-    .await
-    {
-        Ok(l) => Box::new(l),
+
+    let policy = match Policy::new(config.policies) {
+        Ok(policy) => policy,
         Err(e) => {
-            error!("Couldn't create listener: {e}");
+            error!("Couldn't load access control rules: {e}");
             return Err(WebMQError::Unrecoverable.into());
         }
     };
 
-    listener.start().await;
+    let dispatcher = Arc::new(dispatcher);
+    if let Some(interval) = config.messaging.expiry_sweep_interval() {
+        dispatcher.clone().sweep(interval);
+    }
+
+    let adapter = Arc::new(HyperAdapter {
+        dispatcher,
+        authenticator: Arc::new(authenticator),
+        policy: Arc::new(policy),
+        limits: Arc::new(limits),
+        streams: Arc::default(),
+        max_wait: config.messaging.max_wait(),
+        visibility_timeout: config.messaging.visibility_timeout(),
+    });
+
+    let mut listeners = vec![];
+    for listener in config.network.listeners {
+        match create_listeners(listener, &config.network.http2, adapter.clone()).await {
+            Ok(l) => listeners.extend(l),
+            Err(e) => {
+                error!("Couldn't create listener: {e}");
+                return Err(WebMQError::Unrecoverable.into());
+            }
+        }
+    }
+
+    join_all(listeners.iter().map(|listener| listener.start())).await;
 
     Ok(())
 }
 
-fn create_memory_queue() -> Box<dyn AsyncQueue<Vec<u8>> + Send> {
-    Box::new(MemoryQueue::new())
+async fn create_listeners(
+    settings: ListenerSettings,
+    http2: &Http2Settings,
+    adapter: Arc<HyperAdapter>,
+) -> Result<Vec<Box<dyn AsyncStart>>, WebMQError> {
+    let mut listeners: Vec<Box<dyn AsyncStart>> = vec![];
+    for addr in resolve_addresses(&settings).await? {
+        match &settings.tls {
+            Some(tls) => {
+                let listener = HttpsListener::new(addr, tls.clone(), http2, adapter.clone()).await?;
+                info!("Listening for HTTPS on {addr}");
+                listeners.push(Box::new(listener));
+            }
+            None => {
+                let listener = HttpListener::new(addr, http2, adapter.clone()).await?;
+                info!("Listening for HTTP on {addr}");
+                listeners.push(Box::new(listener));
+            }
+        }
+    }
+
+    Ok(listeners)
+}
+
+async fn resolve_addresses(settings: &ListenerSettings) -> Result<Vec<SocketAddr>, WebMQError> {
+    let mut addresses = vec![];
+    for address in &settings.addresses {
+        let host = address.trim_start_matches('[').trim_end_matches(']');
+        match lookup_host((host, settings.port)).await {
+            Ok(resolved) => {
+                for addr in resolved {
+                    if !addresses.contains(&addr) {
+                        addresses.push(addr);
+                    }
+                }
+            }
+            Err(e) => {
+                return Err(WebMQError::Config(format!(
+                    "Couldn't resolve address {address}: {e}"
+                )));
+            }
+        }
+    }
+
+    Ok(addresses)
+}
+
+fn create_queue_factory(storage: StorageSettings) -> QueueFac {
+    match storage.backend {
+        StorageBackend::Memory => Box::pin(|_: &str| Box::pin(create_memory_queue()) as BoxFuture<_>),
+        StorageBackend::File => Box::pin(move |queue: &str| {
+            Box::pin(create_file_queue(storage.clone(), queue.to_string())) as BoxFuture<_>
+        }),
+    }
+}
+
+async fn create_memory_queue() -> Result<BoxedQueue, WebMQError> {
+    Ok(Box::new(MemoryQueue::new()))
+}
+
+/// Opening a queue scans and may truncate its segments, so it runs on the
+/// blocking thread pool.
+async fn create_file_queue(storage: StorageSettings, queue: String) -> Result<BoxedQueue, WebMQError> {
+    let directory = queue_directory(Path::new(storage.directory.as_str()), &queue)?;
+    let opened = tokio::task::spawn_blocking(move || FileQueue::<Message>::open(&directory, &storage));
+    match opened.await {
+        Ok(queue) => Ok(Box::new(queue?)),
+        Err(e) => Err(WebMQError::Data(format!("Could not open queue {queue}: {e}"))),
+    }
 }
\ No newline at end of file
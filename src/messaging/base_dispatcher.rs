@@ -1,56 +1,921 @@
-use std::{collections::HashMap, pin::Pin};
+use std::{collections::{BTreeSet, HashMap, HashSet}, future::Future, pin::Pin, sync::Arc, time::Duration};
 
 use async_trait::async_trait;
-use tokio::sync::Mutex;
+use dashmap::DashMap;
+use futures_util::future::BoxFuture;
+use log::{info, warn};
+use tokio::{sync::{Mutex, Notify, OwnedMutexGuard}, time::Instant};
+use uuid::Uuid;
 
-use crate::core::{errors::WebMQError, traits::AsyncQueue};
+use crate::core::{
+    config::queue::QueueSettings,
+    errors::WebMQError,
+    models::{
+        delivery::{Delivery, Reserved},
+        message::{DeliveryFailure, Message, unix_millis},
+        queue_info::QueueInfo,
+    },
+    traits::AsyncQueue,
+};
 
 use crate::core::traits::MessagingDispatcher;
 
-type QueueFac = Pin<Box<dyn Fn() -> Box<dyn AsyncQueue<Vec<u8>> + Send> + Send + Sync>>;
+pub type BoxedQueue = Box<dyn AsyncQueue<Message> + Send>;
+/// Builds the queue behind a name. Opening a queue may take a while, so it
+/// happens in the returned future rather than in the call itself.
+pub type QueueFac = Pin<Box<dyn Fn(&str) -> BoxFuture<'static, Result<BoxedQueue, WebMQError>> + Send + Sync>>;
 
+type SharedQueue = Arc<Mutex<QueueState>>;
+type QueueGuard = OwnedMutexGuard<QueueState>;
+
+const EXPIRED_REASON: &str = "Visibility timeout expired";
+const NACK_REASON: &str = "Released by consumer";
+const REJECT_REASON: &str = "Rejected by consumer";
+
+/// A delivery waiting for its acknowledgement. The message stays reserved in
+/// its queue until it is acknowledged or has been put somewhere else, so with
+/// file storage it is delivered again if the broker stops in the meantime.
+struct Lease {
+    message: Message,
+    token: u64,
+    expires_at: Instant,
+}
+
+struct QueueState {
+    queue: BoxedQueue,
+    leases: HashMap<String, Lease>,
+    settings: QueueSettings,
+    /// Reserved messages waiting to be moved to the dead letter or expiry
+    /// queue. They are only released once they have been moved, so nothing
+    /// is lost if whoever set them aside goes away first.
+    dead: Vec<Reserved<Message>>,
+    expired: Vec<Reserved<Message>>,
+    /// Held while the messages above are being moved.
+    moving: Arc<Mutex<()>>,
+    /// Set once the queue is deleted, for anyone who got hold of it before.
+    deleted: bool,
+}
+
+/// Every queue has a lock of its own, so work on one queue never waits for
+/// another. No operation holds more than one queue lock at a time.
 pub struct BaseMessagingDispatcher {
-    queues: Mutex<HashMap<String, Box<dyn AsyncQueue<Vec<u8>> + Send>>>,
-    queue_factory: QueueFac
+    queues: DashMap<String, SharedQueue>,
+    watchers: DashMap<String, Arc<Notify>>,
+    topics: DashMap<String, BTreeSet<String>>,
+    queue_factory: QueueFac,
+    queue_settings: DashMap<String, QueueSettings>,
+    /// Settings from the configuration, which outlive the queues they are for.
+    configured_settings: HashMap<String, QueueSettings>,
+    /// Held while a queue is being built, so the same queue is never opened
+    /// twice. Queues with different names are built independently.
+    creating: DashMap<String, Arc<Mutex<()>>>,
 }
 
 #[async_trait]
-impl MessagingDispatcher<String, Vec<u8>> for BaseMessagingDispatcher {
-    async fn consume(&mut self, queue: String) -> Result<Vec<u8>, WebMQError> {
-        let mut queues = self.queues.lock().await;
+impl MessagingDispatcher<String, Message> for BaseMessagingDispatcher {
+    async fn consume(&self, queue: String, visibility_timeout: Duration) -> Result<Delivery<Message>, WebMQError> {
+        let mut deliveries = self.consume_many(queue, 1, usize::MAX, visibility_timeout).await?;
+        Ok(deliveries.remove(0))
+    }
+
+    async fn consume_many(
+        &self,
+        queue: String,
+        max: usize,
+        max_bytes: usize,
+        visibility_timeout: Duration,
+    ) -> Result<Vec<Delivery<Message>>, WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Err(WebMQError::Data(format!("No messages in queue {queue}")));
+        };
+        // Messages are taken out of storage and leased in one go, so a caller
+        // going away in between can't leave them in neither place.
+        let res = detach(async move {
+            state.requeue_expired().await;
+            let now = unix_millis();
+            loop {
+                let messages = match state.queue.reserve_many(max, max_bytes).await {
+                    Ok(messages) => messages,
+                    Err(e) => break Err(WebMQError::Data(e.to_string())),
+                };
+                let (stale, messages): (Vec<_>, Vec<_>) =
+                    messages.into_iter().partition(|reserved| reserved.data.is_expired(now));
+                state.expired.extend(stale);
+                if messages.is_empty() {
+                    continue;
+                }
 
-        if let Some(mut_queue) = queues.get_mut(queue.as_str()) {
-            match mut_queue.pop().await {
-                Ok(r) => Ok(r),
-                Err(e) => Err(WebMQError::Data(e.to_string()))
+                let expires_at = Instant::now() + visibility_timeout;
+                let deliveries = messages
+                    .into_iter()
+                    .map(|Reserved { token, data: mut message }| {
+                        message.delivery_count += 1;
+                        let receipt = Uuid::new_v4().to_string();
+                        state.leases.insert(receipt.clone(), Lease {
+                            message: message.clone(),
+                            token,
+                            expires_at,
+                        });
+                        Delivery { receipt, data: message }
+                    })
+                    .collect();
+                break Ok(deliveries);
             }
-        } else {
-            Err(WebMQError::Data(format!("No messages in queue {queue}")))
+        })
+        .await?;
+
+        self.move_aside(&queue).await;
+        res
+    }
+
+    async fn publish(&self, queue: String, mut data: Message) -> Option<WebMQError> {
+        let mut state = match self.lock_or_create(&queue).await {
+            Ok(state) => state,
+            Err(e) => return Some(e),
+        };
+        state.apply_ttl(&mut data);
+        if let Some(e) = state.queue.push(data).await {
+            return Some(WebMQError::Data(format!("Could not publish to queue {queue}: {e}")));
         }
+        drop(state);
+
+        self.notify(&queue);
+        None
     }
 
-    async fn publish(&mut self, queue: String, data: Vec<u8>) -> Option<WebMQError> {
-        let mut queues = self.queues.lock().await;
+    async fn publish_batch(&self, queue: String, mut data: Vec<Message>) -> Option<WebMQError> {
+        let mut state = match self.lock_or_create(&queue).await {
+            Ok(state) => state,
+            Err(e) => return Some(e),
+        };
+        let count = data.len();
+        data.iter_mut().for_each(|message| state.apply_ttl(message));
+        if let Some(e) = state.queue.push_many(data).await {
+            return Some(WebMQError::Data(format!("Could not publish to queue {queue}: {e}")));
+        }
+        drop(state);
 
-        if !queues.contains_key(&queue) {
-            queues.insert(queue.clone(), self.queue_factory.as_ref()());
+        // Every call wakes another waiting consumer.
+        for _ in 0..count {
+            self.notify(&queue);
         }
+        None
+    }
+
+    async fn ack(&self, queue: String, receipt: String) -> Option<WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Some(WebMQError::Data(format!("No such queue {queue}")));
+        };
+        let name = queue.clone();
+        let res = detach(async move {
+            state.requeue_expired().await;
+            match state.leases.remove(&receipt) {
+                Some(lease) => {
+                    state.forget(lease.token).await;
+                    None
+                }
+                None => Some(WebMQError::Data(format!(
+                    "No outstanding delivery {receipt} on queue {name}"
+                ))),
+            }
+        })
+        .await;
+
+        self.move_aside(&queue).await;
+        res.unwrap_or_else(Some)
+    }
 
-        if let Some(mut_queue) = queues.get_mut(&queue) {
-            mut_queue.push(data).await;
+    async fn nack(&self, queue: String, receipt: String) -> Option<WebMQError> {
+        self.release(queue, receipt, NACK_REASON.to_string()).await
+    }
+
+    async fn reject(&self, queue: String, receipt: String, reason: Option<String>) -> Option<WebMQError> {
+        let reason = reason.unwrap_or_else(|| REJECT_REASON.to_string());
+        self.release(queue, receipt, reason).await
+    }
+
+    async fn return_delivery(&self, queue: String, receipt: String) -> Option<WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Some(WebMQError::Data(format!("No such queue {queue}")));
+        };
+        let name = queue.clone();
+        let res = detach(async move {
+            let Some(lease) = state.leases.get(&receipt) else {
+                return Some(WebMQError::Data(format!(
+                    "No outstanding delivery {receipt} on queue {name}"
+                )));
+            };
+
+            let mut message = lease.message.clone();
+            message.delivery_count = message.delivery_count.saturating_sub(1);
+            if let Some(e) = state.queue.push(message).await {
+                return Some(WebMQError::Data(format!("Could not redeliver to queue {name}: {e}")));
+            }
+            if let Some(lease) = state.leases.remove(&receipt) {
+                state.forget(lease.token).await;
+            }
             None
-        } else {
-            Some(WebMQError::Data(format!("Could not publish to queue {queue}.")))
+        })
+        .await
+        .unwrap_or_else(Some);
+
+        if res.is_none() {
+            self.notify(&queue);
+        }
+        res
+    }
+
+    async fn redrive(&self, queue: String) -> Result<usize, WebMQError> {
+        let Some(state) = self.lock(&queue).await else {
+            return Err(WebMQError::Data(format!("No such queue {queue}")));
+        };
+        let pending = state.queue.stats().messages;
+        drop(state);
+
+        let is_dead_letter_queue = self
+            .queue_settings
+            .iter()
+            .any(|settings| settings.dead_letter_queue.as_deref() == Some(queue.as_str()));
+        if !is_dead_letter_queue {
+            return Err(WebMQError::Config(format!("Queue {queue} is not a dead letter queue")));
+        }
+
+        // Messages are moved one at a time and only removed once their source
+        // has them, so a failure part way through can't lose any. Whatever is
+        // left behind goes to the back of the queue, and every message present
+        // at the start is looked at once.
+        let mut redriven = 0;
+        for _ in 0..pending {
+            let mut message = {
+                let Some(mut state) = self.lock(&queue).await else {
+                    break;
+                };
+                let Ok(message) = state.queue.peek().await else {
+                    break;
+                };
+                if message.dead_letter_source.is_none() {
+                    if let Some(e) = state.rotate().await {
+                        warn!("Could not return message to queue {queue}: {e}");
+                    }
+                    continue;
+                }
+                message
+            };
+
+            let id = message.id.clone();
+            let source = message.dead_letter_source.take().unwrap_or_default();
+            message.delivery_count = 0;
+            let pushed = match self.lock_or_create(&source).await {
+                Ok(mut source_state) => {
+                    source_state.restart_ttl(&mut message, unix_millis());
+                    source_state.queue.push(message).await.map(|e| WebMQError::Data(e.to_string()))
+                }
+                Err(e) => Some(e),
+            };
+
+            let Some(mut state) = self.lock(&queue).await else {
+                break;
+            };
+            let is_head = state.queue.peek().await.is_ok_and(|head| head.id == id);
+            match pushed {
+                Some(e) => {
+                    warn!("Could not redrive message to queue {source}: {e}");
+                    if is_head && let Some(e) = state.rotate().await {
+                        warn!("Could not return message to queue {queue}: {e}");
+                    }
+                }
+                None => {
+                    // Someone else may have taken the message in the meantime,
+                    // in which case it is now in both places.
+                    if is_head && let Err(e) = state.queue.pop().await {
+                        warn!("Could not remove redriven message from queue {queue}: {e}");
+                    }
+                    drop(state);
+                    self.notify(&source);
+                    redriven += 1;
+                }
+            }
+        }
+
+        Ok(redriven)
+    }
+
+    async fn publish_topic(&self, topic: String, data: Message) -> Result<usize, WebMQError> {
+        let subscriptions = self.subscriptions(topic.clone()).await;
+
+        let mut delivered = 0;
+        let mut failed = vec![];
+        for queue in subscriptions {
+            let pushed = match self.lock_or_create(&queue).await {
+                Ok(mut state) => {
+                    let mut message = data.clone();
+                    state.apply_ttl(&mut message);
+                    state.queue.push(message).await.map(|e| WebMQError::Data(e.to_string()))
+                }
+                Err(e) => Some(e),
+            };
+
+            match pushed {
+                Some(e) => {
+                    warn!("Could not deliver message on topic {topic} to queue {queue}: {e}");
+                    failed.push(queue);
+                }
+                None => {
+                    self.notify(&queue);
+                    delivered += 1;
+                }
+            }
+        }
+
+        if !failed.is_empty() {
+            return Err(WebMQError::Data(format!(
+                "Could not deliver message on topic {topic} to queue(s) {}",
+                failed.join(", ")
+            )));
+        }
+
+        Ok(delivered)
+    }
+
+    async fn subscribe(&self, topic: String, queue: String) -> Option<WebMQError> {
+        let inserted = self
+            .topics
+            .entry(topic.clone())
+            .or_default()
+            .insert(queue.clone());
+
+        if !inserted {
+            return Some(WebMQError::Data(format!(
+                "Queue {queue} is already subscribed to topic {topic}"
+            )));
+        }
+
+        None
+    }
+
+    async fn unsubscribe(&self, topic: String, queue: String) -> Option<WebMQError> {
+        let removed = self
+            .topics
+            .get_mut(&topic)
+            .is_some_and(|mut subscriptions| subscriptions.remove(&queue));
+        if !removed {
+            return Some(WebMQError::Data(format!(
+                "Queue {queue} is not subscribed to topic {topic}"
+            )));
+        }
+
+        self.topics.remove_if(&topic, |_, subscriptions| subscriptions.is_empty());
+        None
+    }
+
+    async fn subscriptions(&self, topic: String) -> Vec<String> {
+        self.topics
+            .get(&topic)
+            .map(|subscriptions| subscriptions.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    async fn watch(&self, queue: String) -> Arc<Notify> {
+        self.watchers.entry(queue).or_default().clone()
+    }
+
+    async fn queues(&self) -> Vec<String> {
+        let mut queues: Vec<String> = self.queues.iter().map(|entry| entry.key().clone()).collect();
+        queues.sort();
+        queues
+    }
+
+    async fn queue_info(&self, queue: String) -> Result<QueueInfo, WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Err(WebMQError::Data(format!("No such queue {queue}")));
+        };
+        let name = queue.clone();
+        let info = detach(async move {
+            state.requeue_expired().await;
+            QueueInfo {
+                name,
+                stats: state.queue.stats(),
+                in_flight: state.leases.len(),
+                settings: state.settings.clone(),
+            }
+        })
+        .await?;
+
+        self.move_aside(&queue).await;
+        Ok(info)
+    }
+
+    async fn create_queue(&self, queue: String, settings: QueueSettings) -> Result<bool, WebMQError> {
+        let (_, created) = self.get_or_create_with(&queue, || settings.clone()).await?;
+        self.queue_settings.insert(queue.clone(), settings.clone());
+
+        if !created && let Some(mut state) = self.lock(&queue).await {
+            state.settings = settings;
+        }
+
+        Ok(created)
+    }
+
+    async fn delete_queue(&self, queue: String) -> Option<WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Some(WebMQError::Data(format!("No such queue {queue}")));
+        };
+
+        // The queue stays registered until its storage is gone, so it can't
+        // be recreated on top of what is left of it.
+        if let Some(e) = state.queue.delete().await {
+            return Some(WebMQError::Data(format!("Could not delete queue {queue}: {e}")));
+        }
+        state.leases.clear();
+        state.deleted = true;
+        self.queues.remove(&queue);
+        // Settings changed through the admin API go with the queue, those
+        // from the configuration apply again if it is recreated.
+        match self.configured_settings.get(&queue) {
+            Some(settings) => {
+                self.queue_settings.insert(queue.clone(), settings.clone());
+            }
+            None => {
+                self.queue_settings.remove(&queue);
+            }
+        }
+        drop(state);
+
+        // Topics would otherwise bring the queue right back.
+        self.topics.retain(|_, subscriptions| {
+            subscriptions.remove(&queue);
+            !subscriptions.is_empty()
+        });
+        // Anyone still waiting on the queue is woken up to find it gone.
+        if let Some((_, watcher)) = self.watchers.remove(&queue) {
+            watcher.notify_waiters();
         }
+
+        None
+    }
+
+    async fn purge_queue(&self, queue: String) -> Result<usize, WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Err(WebMQError::Data(format!("No such queue {queue}")));
+        };
+
+        let res = state.queue.purge().await;
+        res.map_err(|e| WebMQError::Data(format!("Could not purge queue {queue}: {e}")))
+    }
+
+    async fn browse(&self, queue: String, offset: usize, limit: usize) -> Result<Vec<Message>, WebMQError> {
+        let Some(state) = self.lock(&queue).await else {
+            return Err(WebMQError::Data(format!("No such queue {queue}")));
+        };
+
+        let res = state.queue.browse(offset, limit).await;
+        res.map_err(|e| WebMQError::Data(format!("Could not browse queue {queue}: {e}")))
     }
 }
 
 impl BaseMessagingDispatcher
 {
-    pub fn new(queue_factory: QueueFac) -> BaseMessagingDispatcher {
+    pub fn new(queue_factory: QueueFac, queue_settings: HashMap<String, QueueSettings>) -> BaseMessagingDispatcher {
         BaseMessagingDispatcher {
-            queues: HashMap::new().into(),
-            queue_factory
+            queues: DashMap::new(),
+            watchers: DashMap::new(),
+            topics: DashMap::new(),
+            queue_factory,
+            queue_settings: queue_settings.clone().into_iter().collect(),
+            configured_settings: queue_settings,
+            creating: DashMap::new(),
+        }
+    }
+
+    /// Reopens queues that already exist in storage so their messages can be
+    /// consumed before anything new is published to them.
+    pub async fn restore(&mut self, queue_names: Vec<String>) -> Option<WebMQError> {
+        for queue in queue_names {
+            if let Err(e) = self.get_or_create(&queue).await {
+                return Some(e);
+            }
+        }
+
+        None
+    }
+
+    /// Binds the subscriptions defined in the configuration to their topics.
+    pub fn restore_subscriptions(&mut self, topics: HashMap<String, Vec<String>>) {
+        for (topic, queues) in topics {
+            self.topics.entry(topic).or_default().extend(queues);
         }
     }
-}
\ No newline at end of file
+
+    fn get(&self, queue: &str) -> Option<SharedQueue> {
+        self.queues.get(queue).map(|shared| shared.clone())
+    }
+
+    async fn get_or_create(&self, queue: &str) -> Result<SharedQueue, WebMQError> {
+        let settings = || self.queue_settings.get(queue).map(|settings| settings.clone()).unwrap_or_default();
+        self.get_or_create_with(queue, settings).await.map(|(shared, _)| shared)
+    }
+
+    /// Locks `queue` if it exists. A queue deleted while waiting for its lock
+    /// is looked up again.
+    async fn lock(&self, queue: &str) -> Option<QueueGuard> {
+        loop {
+            let state = self.get(queue)?.lock_owned().await;
+            if !state.deleted {
+                return Some(state);
+            }
+        }
+    }
+
+    /// Locks `queue`, creating it if it doesn't exist or was deleted while
+    /// waiting for its lock.
+    async fn lock_or_create(&self, queue: &str) -> Result<QueueGuard, WebMQError> {
+        loop {
+            let state = self.get_or_create(queue).await?.lock_owned().await;
+            if !state.deleted {
+                return Ok(state);
+            }
+        }
+    }
+
+    /// Returns `queue`, creating it with `settings` if it doesn't exist yet,
+    /// along with whether it was created.
+    async fn get_or_create_with(
+        &self,
+        queue: &str,
+        settings: impl FnOnce() -> QueueSettings,
+    ) -> Result<(SharedQueue, bool), WebMQError> {
+        if let Some(shared) = self.get(queue) {
+            return Ok((shared, false));
+        }
+
+        let creating = self.creating.entry(queue.to_string()).or_default().clone();
+        let res = self.create(queue, settings, creating).await;
+        self.creating.remove_if(queue, |_, creating| Arc::strong_count(creating) == 1);
+        res
+    }
+
+    /// Builds `queue` while holding `creating`. The factory may touch the
+    /// disk, so it runs without holding on to a shard of `queues`, which would
+    /// stall every other queue in it.
+    async fn create(
+        &self,
+        queue: &str,
+        settings: impl FnOnce() -> QueueSettings,
+        creating: Arc<Mutex<()>>,
+    ) -> Result<(SharedQueue, bool), WebMQError> {
+        let creating = creating.lock_owned().await;
+        if let Some(shared) = self.get(queue) {
+            return Ok((shared, false));
+        }
+
+        // The lock is held until the queue is registered, or until it has been
+        // opened if the caller stops waiting for it, so nobody opens it a
+        // second time in the meantime.
+        let opening = self.queue_factory.as_ref()(queue);
+        let (q, creating) = detach(async move { (opening.await, creating) }).await?;
+        let shared = Arc::new(Mutex::new(QueueState::new(q?, settings())));
+        let shared = self.queues.entry(queue.to_string()).or_insert(shared).clone();
+        drop(creating);
+
+        Ok((shared, true))
+    }
+
+    async fn release(&self, queue: String, receipt: String, reason: String) -> Option<WebMQError> {
+        let Some(mut state) = self.lock(&queue).await else {
+            return Some(WebMQError::Data(format!("No such queue {queue}")));
+        };
+        let name = queue.clone();
+        let res = detach(async move {
+            state.requeue_expired().await;
+            let Some(lease) = state.leases.remove(&receipt) else {
+                return Err(WebMQError::Data(format!(
+                    "No outstanding delivery {receipt} on queue {name}"
+                )));
+            };
+            match state.requeue(receipt, lease, reason).await {
+                Ok(Some(message)) => {
+                    state.dead.push(message);
+                    Ok(false)
+                }
+                Ok(None) => Ok(true),
+                Err(e) => Err(WebMQError::Data(format!("Could not redeliver to queue {name}: {e}"))),
+            }
+        })
+        .await
+        .and_then(|res| res);
+
+        if let Ok(true) = res {
+            self.notify(&queue);
+        }
+        self.move_aside(&queue).await;
+        res.err()
+    }
+
+    /// Moves the messages `queue` has set aside to its dead letter and expiry
+    /// queues and releases them from `queue` once they have been moved.
+    async fn move_aside(&self, queue: &str) {
+        let Some(state) = self.lock(queue).await else {
+            return;
+        };
+        if state.dead.is_empty() && state.expired.is_empty() {
+            return;
+        }
+        let moving = state.moving.clone();
+        drop(state);
+
+        // Only one caller moves the messages of a queue at a time, so they
+        // aren't moved twice. A caller going away releases the lock and leaves
+        // the messages for the next one.
+        let _moving = moving.lock().await;
+        let Some(state) = self.lock(queue).await else {
+            return;
+        };
+        let (dead, expired) = (state.dead.clone(), state.expired.clone());
+        drop(state);
+
+        let mut moved = self.dead_letter(queue, dead).await;
+        moved.extend(self.expire(queue, expired).await);
+        if moved.is_empty() {
+            return;
+        }
+
+        let Some(mut state) = self.lock(queue).await else {
+            return;
+        };
+        state.dead.retain(|reserved| !moved.contains(&reserved.token));
+        state.expired.retain(|reserved| !moved.contains(&reserved.token));
+        if let Some(e) = state.queue.release(moved.into_iter().collect()).await {
+            warn!("Could not release moved messages from queue {queue}: {e}");
+        }
+    }
+
+    /// Moves messages which ran out of deliveries on `source` to its dead
+    /// letter queue, or drops them if it doesn't have one. Returns the tokens
+    /// of the messages which are done with. Those which couldn't be moved stay
+    /// set aside on `source` and are tried again later.
+    async fn dead_letter(&self, source: &str, messages: Vec<Reserved<Message>>) -> HashSet<u64> {
+        if messages.is_empty() {
+            return HashSet::new();
+        }
+
+        let dead_letter_queue = self
+            .queue_settings
+            .get(source)
+            .and_then(|settings| settings.dead_letter_queue.clone());
+        let Some(dead_letter_queue) = dead_letter_queue else {
+            warn!(
+                "Dropping {} message(s) from queue {source} after exceeding the maximum delivery count",
+                messages.len()
+            );
+            return messages.iter().map(|reserved| reserved.token).collect();
+        };
+
+        let mut state = match self.lock_or_create(&dead_letter_queue).await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(
+                    "Could not move {} message(s) from queue {source} to {dead_letter_queue}: {e}",
+                    messages.len()
+                );
+                return HashSet::new();
+            }
+        };
+        let mut moved = HashSet::new();
+        let now = unix_millis();
+        for reserved in messages.iter() {
+            let mut message = reserved.data.clone();
+            message.delivery_count = 0;
+            message.dead_letter_source = Some(source.to_string());
+            state.restart_ttl(&mut message, now);
+            match state.queue.push(message).await {
+                Some(e) => warn!("Could not move message from queue {source} to {dead_letter_queue}: {e}"),
+                None => {
+                    moved.insert(reserved.token);
+                }
+            }
+        }
+        drop(state);
+
+        if !moved.is_empty() {
+            info!("Moved messages from queue {source} to dead letter queue {dead_letter_queue}");
+            self.notify(&dead_letter_queue);
+        }
+        moved
+    }
+
+    /// Moves messages which expired on `source` to its expiry queue, or drops
+    /// them if it doesn't have one. Returns the tokens of the messages which
+    /// are done with, like `dead_letter`.
+    async fn expire(&self, source: &str, messages: Vec<Reserved<Message>>) -> HashSet<u64> {
+        if messages.is_empty() {
+            return HashSet::new();
+        }
+
+        let expiry_queue = self
+            .queue_settings
+            .get(source)
+            .and_then(|settings| settings.expiry_queue.clone());
+        let Some(expiry_queue) = expiry_queue else {
+            info!("Dropped {} expired message(s) from queue {source}", messages.len());
+            return messages.iter().map(|reserved| reserved.token).collect();
+        };
+
+        let mut state = match self.lock_or_create(&expiry_queue).await {
+            Ok(state) => state,
+            Err(e) => {
+                warn!(
+                    "Could not move {} expired message(s) from queue {source} to {expiry_queue}: {e}",
+                    messages.len()
+                );
+                return HashSet::new();
+            }
+        };
+        let count = messages.len();
+        let now = unix_millis();
+        let moved = messages
+            .iter()
+            .map(|reserved| {
+                let mut message = reserved.data.clone();
+                message.delivery_count = 0;
+                state.restart_ttl(&mut message, now);
+                message
+            })
+            .collect();
+        if let Some(e) = state.queue.push_many(moved).await {
+            warn!("Could not move expired messages from queue {source} to {expiry_queue}: {e}");
+            return HashSet::new();
+        }
+        drop(state);
+
+        info!("Moved {count} expired message(s) from queue {source} to {expiry_queue}");
+        for _ in 0..count {
+            self.notify(&expiry_queue);
+        }
+        messages.iter().map(|reserved| reserved.token).collect()
+    }
+
+    /// Starts dropping expired messages from every queue every `interval`, so
+    /// they don't pile up in queues nobody consumes from, or behind messages
+    /// which live longer.
+    /// Messages which couldn't be moved to a dead letter or expiry queue
+    /// before are tried again, and idle watchers are dropped along the way.
+    pub fn sweep(self: Arc<Self>, interval: Duration) {
+        tokio::task::spawn(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                self.prune_watchers();
+
+                let queues: Vec<String> = self.queues.iter().map(|entry| entry.key().clone()).collect();
+                for queue in queues {
+                    let Some(mut state) = self.lock(&queue).await else {
+                        continue;
+                    };
+                    state.reserve_expired(unix_millis()).await;
+                    drop(state);
+                    self.move_aside(&queue).await;
+                }
+            }
+        });
+    }
+
+    /// Wakes a consumer waiting on `queue`. Watchers nobody holds on to
+    /// anymore are dropped instead, so they don't pile up for queue names
+    /// which were only ever waited on once.
+    fn notify(&self, queue: &str) {
+        if self.watchers.remove_if(queue, |_, watcher| Arc::strong_count(watcher) == 1).is_some() {
+            return;
+        }
+        if let Some(watcher) = self.watchers.get(queue) {
+            watcher.notify_one();
+        }
+    }
+
+    fn prune_watchers(&self) {
+        self.watchers.retain(|_, watcher| Arc::strong_count(watcher) > 1);
+    }
+}
+
+impl QueueState {
+    fn new(queue: BoxedQueue, settings: QueueSettings) -> QueueState {
+        QueueState {
+            queue,
+            leases: HashMap::new(),
+            settings,
+            dead: vec![],
+            expired: vec![],
+            moving: Arc::new(Mutex::new(())),
+            deleted: false,
+        }
+    }
+
+    /// Sets the queue's TTL on messages published without one of their own.
+    fn apply_ttl(&self, message: &mut Message) {
+        if let (None, Some(ttl)) = (message.expires_at, self.settings.ttl()) {
+            message.expire_after(ttl);
+        }
+    }
+
+    /// Sets the queue's TTL on a message moved here from another queue,
+    /// counting from `now` rather than from when it was published. Whatever
+    /// expiry it had before is dropped, so it never expires here if the queue
+    /// has no TTL.
+    fn restart_ttl(&self, message: &mut Message, now: u64) {
+        message.expires_at = self.settings.ttl().map(|ttl| now.saturating_add(ttl.as_millis() as u64));
+    }
+
+    /// Sets every expired message in the queue aside.
+    async fn reserve_expired(&mut self, now: u64) {
+        let res = self.queue.reserve_expired(now).await;
+        match res {
+            Ok(messages) => self.expired.extend(messages),
+            Err(e) => warn!("Could not drop expired messages: {e}"),
+        }
+    }
+
+    /// Records a failed delivery and puts the message back into the queue.
+    /// Returns the message, still reserved, instead if it has used up all of
+    /// its deliveries.
+    async fn requeue(
+        &mut self,
+        receipt: String,
+        lease: Lease,
+        reason: String,
+    ) -> Result<Option<Reserved<Message>>, WebMQError> {
+        let mut message = lease.message.clone();
+        message.failures.push(DeliveryFailure::now(reason));
+
+        if self
+            .settings
+            .max_deliveries
+            .is_some_and(|max| message.delivery_count >= max)
+        {
+            return Ok(Some(Reserved { token: lease.token, data: message }));
+        }
+
+        if let Some(e) = self.queue.push(message).await {
+            // It stays leased, so it is tried again once the lease runs out.
+            self.leases.insert(receipt, lease);
+            return Err(WebMQError::Data(e.to_string()));
+        }
+        self.forget(lease.token).await;
+        Ok(None)
+    }
+
+    /// Releases a leased message once it has been dealt with. If that fails it
+    /// is only delivered again after a restart.
+    async fn forget(&mut self, token: u64) {
+        if let Some(e) = self.queue.release(vec![token]).await {
+            warn!("Could not release delivered message: {e}");
+        }
+    }
+
+    /// Moves the message at the front of the queue to the back. It is added
+    /// to the back before it is taken off the front, so a failure in between
+    /// can't lose it.
+    async fn rotate(&mut self) -> Option<WebMQError> {
+        let message = match self.queue.peek().await {
+            Ok(message) => message,
+            Err(e) => return Some(WebMQError::Data(e.to_string())),
+        };
+
+        if let Some(e) = self.queue.push(message).await {
+            return Some(WebMQError::Data(e.to_string()));
+        }
+        self.queue.pop().await.err().map(|e| WebMQError::Data(e.to_string()))
+    }
+
+    /// Puts messages whose visibility timeout has passed without an
+    /// acknowledgement back into the queue, setting aside those that have to
+    /// be dead-lettered instead.
+    async fn requeue_expired(&mut self) {
+        let now = Instant::now();
+        let mut expired: Vec<(String, Instant)> = self
+            .leases
+            .iter()
+            .filter(|(_, lease)| lease.expires_at <= now)
+            .map(|(receipt, lease)| (receipt.clone(), lease.expires_at))
+            .collect();
+        expired.sort_by_key(|(_, expires_at)| *expires_at);
+
+        for (receipt, _) in expired {
+            let Some(lease) = self.leases.remove(&receipt) else {
+                continue;
+            };
+
+            match self.requeue(receipt.clone(), lease, EXPIRED_REASON.to_string()).await {
+                Ok(Some(message)) => self.dead.push(message),
+                Ok(None) => {}
+                Err(e) => warn!("Could not redeliver expired delivery {receipt}: {e}"),
+            }
+        }
+    }
+}
+
+/// Runs `operation` on a task of its own, so it is carried out in full even
+/// if the caller stops waiting for it.
+async fn detach<R: Send + 'static>(operation: impl Future<Output = R> + Send + 'static) -> Result<R, WebMQError> {
+    match tokio::task::spawn(operation).await {
+        Ok(res) => Ok(res),
+        Err(e) => Err(WebMQError::Data(format!("Queue operation failed: {e}"))),
+    }
+}
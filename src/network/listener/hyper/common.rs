@@ -1,33 +1,93 @@
-use http_body_util::Full;
+use std::{convert::Infallible, net::SocketAddr, sync::Arc};
+
+use http_body_util::combinators::BoxBody;
 use hyper::{
     Request, Response,
     body::{Bytes, Incoming},
-    server::conn::http1,
     service::service_fn,
 };
-use hyper_util::rt::{TokioIo, TokioTimer};
-use log::warn;
-use tokio::io::{AsyncRead, AsyncWrite};
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo, TokioTimer},
+    server::conn::auto,
+};
+use log::{error, warn};
+use socket2::{Domain, Protocol, Socket, Type};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
 
-use crate::core::{errors::WebMQError, traits::Adapter};
+use crate::core::{
+    config::http2::Http2Settings, errors::WebMQError, models::identity::ClientIdentity,
+    traits::Adapter,
+};
 
 type Err = WebMQError;
 
 pub type Req = Request<Incoming>;
-pub type Res = Result<Response<Full<Bytes>>, Err>;
+pub type Res = Result<Response<BoxBody<Bytes, Infallible>>, Err>;
 pub type HyperSvc = dyn Adapter<Input = Req, Output = Res> + Send + Sync;
+pub type ConnectionBuilder = auto::Builder<TokioExecutor>;
+
+const LISTEN_BACKLOG: i32 = 1024;
+
+/// Builds a connection builder which serves HTTP/1.1 and HTTP/2, telling them
+/// apart by the connection preface.
+pub fn connection_builder(settings: &Http2Settings) -> ConnectionBuilder {
+    let mut builder = auto::Builder::new(TokioExecutor::new());
+    builder.http1().timer(TokioTimer::new());
+    builder
+        .http2()
+        .timer(TokioTimer::new())
+        .max_concurrent_streams(settings.max_concurrent_streams)
+        .initial_stream_window_size(settings.initial_stream_window_size)
+        .initial_connection_window_size(settings.initial_connection_window_size);
+    builder
+}
 
-pub async fn hyper_http1_handler<S>(stream: S, service: &HyperSvc)
-where
-    S: AsyncRead + AsyncWrite + Unpin,
+/// Serves a connection, attaching `identity` to every request received on it.
+pub async fn hyper_handler<S>(
+    stream: S,
+    service: Arc<HyperSvc>,
+    builder: &ConnectionBuilder,
+    identity: Option<ClientIdentity>,
+) where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
-    let svc = service_fn(async |request| service.call(request).await);
+    let svc = service_fn(move |mut request: Req| {
+        let service = service.clone();
+        if let Some(identity) = &identity {
+            request.extensions_mut().insert(identity.clone());
+        }
+        async move { service.call(request).await }
+    });
     let io = TokioIo::new(stream);
-    if let Err(err) = http1::Builder::new()
-        .timer(TokioTimer::new())
-        .serve_connection(io, svc)
-        .await
-    {
+    if let Err(err) = builder.serve_connection_with_upgrades(io, svc).await {
         warn!("Error in service connection: {}", err);
     }
 }
+
+/// Binds a TCP listener to `addr`. Binding the unspecified IPv6 address `[::]`
+/// accepts IPv4 connections as well.
+pub fn bind_tcp_listener(addr: SocketAddr) -> Result<TcpListener, WebMQError> {
+    match try_bind(addr) {
+        Ok(l) => Ok(l),
+        Err(e) => {
+            error!("Could not create TCP listener on {addr}: {e}");
+            Err(WebMQError::Unrecoverable)
+        }
+    }
+}
+
+fn try_bind(addr: SocketAddr) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(!addr.ip().is_unspecified())?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+
+    TcpListener::from_std(socket.into())
+}
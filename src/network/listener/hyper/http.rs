@@ -1,48 +1,49 @@
 use std::{
-    net::{Ipv4Addr, SocketAddrV4},
+    net::SocketAddr,
     sync::Arc,
 };
 
 use async_trait::async_trait;
 
-use log::{debug, error};
+use log::debug;
 use tokio::{
     net::{TcpListener, TcpStream},
     task::JoinHandle,
 };
 
-use crate::core::{errors::WebMQError, traits::AsyncStart};
+use crate::core::{config::http2::Http2Settings, errors::WebMQError, traits::AsyncStart};
 
-use super::common::{HyperSvc, hyper_http1_handler};
+use super::common::{
+    ConnectionBuilder, HyperSvc, bind_tcp_listener, connection_builder, hyper_handler,
+};
 
 #[derive(Clone)]
 pub struct HttpListener {
     tcp_listener: Arc<TcpListener>,
     service: Arc<HyperSvc>,
+    builder: Arc<ConnectionBuilder>,
 }
 
 impl HttpListener {
-    pub async fn new(ip: Ipv4Addr, port: u16, service: Arc<HyperSvc>) -> Result<Self, WebMQError> {
-        let addr = SocketAddrV4::new(ip, port);
-
-        let tcp_listener = Arc::new(match TcpListener::bind(addr).await {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Could not create TCP listener: {e}");
-                return Err(WebMQError::Unrecoverable);
-            }
-        });
+    pub async fn new(
+        addr: SocketAddr,
+        http2_config: &Http2Settings,
+        service: Arc<HyperSvc>,
+    ) -> Result<Self, WebMQError> {
+        let tcp_listener = Arc::new(bind_tcp_listener(addr)?);
 
         Ok(HttpListener {
             tcp_listener,
             service,
+            builder: Arc::new(connection_builder(http2_config)),
         })
     }
 
     fn spawn_handler_task(&self, tcp_stream: TcpStream) -> JoinHandle<()> {
         let service = self.service.clone();
+        let builder = self.builder.clone();
         tokio::task::spawn(async move {
-            hyper_http1_handler(tcp_stream, service.as_ref()).await;
+            hyper_handler(tcp_stream, service, &builder, None).await;
         })
     }
 }
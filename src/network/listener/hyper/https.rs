@@ -1,7 +1,6 @@
 use std::{
     error::Error,
-    net::{Ipv4Addr, SocketAddrV4},
-    path::Path,
+    net::SocketAddr,
     sync::Arc,
 };
 
@@ -15,37 +14,48 @@ use tokio::{
 };
 
 use crate::{
-    core::{config::tls::TlsSettings, errors::WebMQError, traits::AsyncStart},
-    network::tls::acceptor::create_tls_acceptor,
+    core::{
+        config::{
+            http2::Http2Settings,
+            tls::{PlaintextMode, TlsSettings},
+        },
+        errors::WebMQError,
+        traits::AsyncStart,
+    },
+    network::tls::{acceptor::create_tls_acceptor, identity::client_identity},
 };
 
-use super::common::hyper_http1_handler;
-
-use super::common::HyperSvc;
+use super::{
+    common::{ConnectionBuilder, HyperSvc, bind_tcp_listener, connection_builder, hyper_handler},
+    redirect::HttpsRedirect,
+};
 
-const TLS_CLIENT_HELLO_HEAD_SIZE: usize = 3;
-const TLS_CLIENT_HELLO_HEAD: [u8; TLS_CLIENT_HELLO_HEAD_SIZE] = [0x16, 0x03, 0x01];
+/// Content type of the TLS record a client hello arrives in. The version
+/// following it varies between clients, so it isn't checked.
+const TLS_HANDSHAKE_RECORD: u8 = 0x16;
 
 #[derive(Clone)]
 pub struct HttpsListener {
     tls_acceptor: TlsAcceptor,
     tcp_listener: Arc<TcpListener>,
     service: Arc<HyperSvc>,
+    builder: Arc<ConnectionBuilder>,
+    plaintext: PlaintextMode,
 }
 
 impl HttpsListener {
     pub async fn new(
-        ip: Ipv4Addr,
-        port: u16,
+        addr: SocketAddr,
         tls_config: TlsSettings,
+        http2_config: &Http2Settings,
         service: Arc<HyperSvc>,
     ) -> Result<Self, WebMQError> {
-        let addr = SocketAddrV4::new(ip, port);
+        if tls_config.plaintext == PlaintextMode::Serve && tls_config.client_auth.is_some() {
+            error!("Plaintext mode serve can't be combined with client_auth, as it would let clients skip certificate verification");
+            return Err(WebMQError::Unrecoverable);
+        }
 
-        let tls_acceptor = match create_tls_acceptor(
-            Path::new(tls_config.certificate.as_str()),
-            Path::new(tls_config.private_key.as_str()),
-        ) {
+        let tls_acceptor = match create_tls_acceptor(&tls_config) {
             Ok(l) => l,
             Err(e) => {
                 error!("{e}");
@@ -54,27 +64,22 @@ impl HttpsListener {
         };
         info!("Initialized TLS acceptor");
 
-        let tcp_listener = Arc::new(match TcpListener::bind(addr).await {
-            Ok(l) => l,
-            Err(e) => {
-                error!("Could not create TCP listener: {e}");
-                return Err(WebMQError::Unrecoverable);
-            }
-        });
+        let tcp_listener = Arc::new(bind_tcp_listener(addr)?);
 
         Ok(HttpsListener {
             tls_acceptor,
             tcp_listener,
             service,
+            builder: Arc::new(connection_builder(http2_config)),
+            plaintext: tls_config.plaintext,
         })
     }
 
-    fn spawn_handler_task(&self, mut tcp_stream: TcpStream) -> JoinHandle<()> {
+    fn spawn_handler_task(&self, tcp_stream: TcpStream) -> JoinHandle<()> {
         let handler = self.clone();
         tokio::task::spawn(async move {
             if let Some(err) = is_tls(&tcp_stream).await {
-                warn!("{err}");
-                discard_stream(&mut tcp_stream).await;
+                handler.handle_plaintext_connection(tcp_stream, err).await;
                 return;
             };
 
@@ -82,10 +87,34 @@ impl HttpsListener {
         })
     }
 
+    async fn handle_plaintext_connection(&self, mut tcp_stream: TcpStream, err: impl Error) {
+        match self.plaintext {
+            PlaintextMode::Reject => {
+                warn!("{err}");
+                discard_stream(&mut tcp_stream).await;
+            }
+            PlaintextMode::Serve => {
+                debug!("{err}, serving plain HTTP");
+                hyper_handler(tcp_stream, self.service.clone(), &self.builder, None).await;
+            }
+            PlaintextMode::Redirect => {
+                debug!("{err}, redirecting to HTTPS");
+                hyper_handler(tcp_stream, Arc::new(HttpsRedirect), &self.builder, None).await;
+            }
+        }
+    }
+
     async fn handle_tls_connection(&self, tcp_stream: TcpStream) {
         match self.tls_acceptor.accept(tcp_stream).await {
             Ok(stream) => {
-                hyper_http1_handler(stream, self.service.as_ref()).await;
+                let identity = client_identity(stream.get_ref().1);
+                if let Some(identity) = &identity {
+                    debug!(
+                        "Authenticated client {identity} ({})",
+                        identity.alternative_names.join(", ")
+                    );
+                }
+                hyper_handler(stream, self.service.clone(), &self.builder, identity).await;
             }
             Err(e) => warn!("Error during TLS handshake: {e}"),
         }
@@ -111,8 +140,8 @@ impl AsyncStart for HttpsListener {
 }
 
 async fn is_tls(stream: &TcpStream) -> Option<impl Error + use<>> {
-    let mut buf = [0u8; TLS_CLIENT_HELLO_HEAD_SIZE];
-    if stream.peek(&mut buf).await.is_ok() && buf != TLS_CLIENT_HELLO_HEAD {
+    let mut buf = [0u8; 1];
+    if stream.peek(&mut buf).await.is_ok_and(|n| n >= 1 && buf[0] != TLS_HANDSHAKE_RECORD) {
         let Ok(peer_addr) = stream.peer_addr() else {
             return Some(WebMQError::TLS(
                 "Received non-TLS data from peer".to_owned(),
@@ -1,3 +1,4 @@
 mod common;
 pub mod http;
 pub mod https;
+mod redirect;
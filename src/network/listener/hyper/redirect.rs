@@ -0,0 +1,41 @@
+use async_trait::async_trait;
+use http_body_util::{BodyExt, Empty};
+use hyper::{Response, StatusCode, header};
+
+use crate::core::traits::Adapter;
+
+use super::common::{Req, Res};
+
+/// Answers every request with a permanent redirect to the same URL over
+/// HTTPS.
+pub struct HttpsRedirect;
+
+#[async_trait]
+impl Adapter for HttpsRedirect {
+    type Input = Req;
+    type Output = Res;
+
+    async fn call(&self, request: Self::Input) -> Self::Output {
+        let host = request
+            .uri()
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| request.headers().get(header::HOST).and_then(|host| host.to_str().ok()));
+
+        let response = match host {
+            Some(host) => {
+                let path = request
+                    .uri()
+                    .path_and_query()
+                    .map(|path| path.as_str())
+                    .unwrap_or("/");
+                Response::builder()
+                    .status(StatusCode::PERMANENT_REDIRECT)
+                    .header(header::LOCATION, format!("https://{host}{path}"))
+            }
+            None => Response::builder().status(StatusCode::BAD_REQUEST),
+        };
+
+        Ok(response.body(Empty::new().boxed()).unwrap())
+    }
+}
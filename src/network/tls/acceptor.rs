@@ -3,66 +3,243 @@ use std::{path::Path, sync::Arc};
 use log::{error, info};
 use tls_listener::rustls::{
     TlsAcceptor,
-    rustls::pki_types::{CertificateDer, PrivateKeyDer},
+    rustls::pki_types::{
+        CertificateDer, CertificateRevocationListDer, PrivateKeyDer, pem::PemObject,
+    },
 };
 
-use rustls::ServerConfig;
+use rustls::{
+    RootCertStore, ServerConfig, SignatureAlgorithm,
+    crypto::CryptoProvider,
+    server::{WebPkiClientVerifier, danger::ClientCertVerifier},
+    sign::{CertifiedKey, SigningKey},
+};
+
+use crate::{
+    core::{
+        config::tls::{ClientAuthMode, ClientAuthSettings, KeyAlgorithm, TlsSettings},
+        errors::WebMQError,
+    },
+    utils::file::get_file_buffer,
+};
+
+use super::resolver::ReloadingCertResolver;
+
+const PEM_PREFIX: &[u8] = b"-----BEGIN";
+
+pub fn create_tls_acceptor(tls_config: &TlsSettings) -> Result<TlsAcceptor, WebMQError> {
+    let resolver = Arc::new(ReloadingCertResolver::new(tls_config)?);
+    resolver.clone().watch(tls_config.reload_interval());
+
+    let builder = ServerConfig::builder();
+    let builder = match &tls_config.client_auth {
+        Some(client_auth) => builder.with_client_cert_verifier(create_client_verifier(client_auth)?),
+        None => builder.with_no_client_auth(),
+    };
 
-use crate::{core::errors::WebMQError, utils::file::get_file_buffer};
+    let mut config = builder.with_cert_resolver(resolver);
+    config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(Arc::new(config).into())
+}
 
-pub fn create_tls_acceptor(
+/// Loads a certificate chain along with the private key belonging to it.
+pub fn load_certified_key(
     certificate_path: &Path,
     private_key_path: &Path,
-) -> Result<TlsAcceptor, WebMQError> {
-    let certificate = match load_certificate(certificate_path) {
-        Ok(cert) => cert.into_owned(),
+    algorithm: KeyAlgorithm,
+) -> Result<CertifiedKey, WebMQError> {
+    let certificates = match load_certificates(certificate_path) {
+        Ok(certs) => certs,
         Err(e) => {
             error!("{e}");
             return Err(e);
         }
     };
+    let certificate_count = certificates.len();
     let certificate_path = certificate_path.to_string_lossy();
-    info!("Loaded certificate from {certificate_path}");
+    info!("Loaded {certificate_count} certificate(s) from {certificate_path}");
 
     let private_key = match load_private_key(private_key_path) {
-        Ok(pkey) => pkey.clone_key(),
+        Ok(pkey) => pkey,
         Err(e) => {
             error!("{e}");
             return Err(e);
         }
     };
+    let signing_key = load_signing_key(private_key, algorithm)?;
     let private_key_path = private_key_path.to_string_lossy();
-    info!("Loaded private key from {private_key_path}");
+    info!(
+        "Loaded {:?} private key from {private_key_path}",
+        signing_key.algorithm()
+    );
+
+    let certified_key = CertifiedKey::new(certificates, signing_key);
+    match certified_key.keys_match() {
+        Ok(()) => Ok(certified_key),
+        Err(e) => Err(WebMQError::TLS(format!(
+            "Certificate {certificate_path} doesn't match private key {private_key_path}: {}",
+            e
+        ))),
+    }
+}
+
+fn create_client_verifier(
+    client_auth: &ClientAuthSettings,
+) -> Result<Arc<dyn ClientCertVerifier>, WebMQError> {
+    let mut roots = RootCertStore::empty();
+    for certificate in load_certificates(Path::new(client_auth.ca_certificates.as_str()))? {
+        if let Err(e) = roots.add(certificate) {
+            return Err(WebMQError::Config(format!(
+                "Couldn't add client CA certificate: {}",
+                e
+            )));
+        }
+    }
 
-    let tls_config = ServerConfig::builder()
-        .with_no_client_auth()
-        .with_single_cert(vec![certificate], private_key);
+    let mut revocation_lists = vec![];
+    for path in &client_auth.revocation_lists {
+        revocation_lists.extend(load_revocation_lists(Path::new(path.as_str()))?);
+    }
+    info!(
+        "Loaded {} client CA certificate(s) and {} revocation list(s)",
+        roots.len(),
+        revocation_lists.len()
+    );
+
+    let builder = WebPkiClientVerifier::builder(Arc::new(roots)).with_crls(revocation_lists);
+    let builder = match client_auth.mode {
+        ClientAuthMode::Required => builder,
+        ClientAuthMode::Optional => builder.allow_unauthenticated(),
+    };
 
-    match tls_config {
-        Ok(config) => Ok(Arc::new(config).into()),
+    match builder.build() {
+        Ok(verifier) => Ok(verifier),
         Err(e) => Err(WebMQError::TLS(format!(
-            "Could not create TLS acceptor: {}",
+            "Could not create client certificate verifier: {}",
             e
         ))),
     }
 }
 
-fn load_certificate(certificate_path: &Path) -> Result<CertificateDer, WebMQError> {
-    match get_file_buffer(certificate_path).map(CertificateDer::from) {
-        Ok(a) => Ok(a),
+/// Loads the certificate chain from a PEM file, leaf first, or a single
+/// DER encoded certificate.
+fn load_certificates(certificate_path: &Path) -> Result<Vec<CertificateDer<'static>>, WebMQError> {
+    let buffer = match get_file_buffer(certificate_path) {
+        Ok(buf) => buf,
+        Err(e) => {
+            return Err(WebMQError::Config(format!(
+                "Couldn't load certificate: {}",
+                e
+            )));
+        }
+    };
+
+    if !is_pem(&buffer) {
+        return Ok(vec![CertificateDer::from(buffer)]);
+    }
+
+    let certificates: Result<Vec<_>, _> = CertificateDer::pem_slice_iter(&buffer).collect();
+    match certificates {
+        Ok(certs) if certs.is_empty() => Err(WebMQError::Config(format!(
+            "No certificates found in {}",
+            certificate_path.to_string_lossy()
+        ))),
+        Ok(certs) => Ok(certs),
         Err(e) => Err(WebMQError::Config(format!(
-            "Couldn't load certificate: {}",
+            "Couldn't parse certificate: {}",
             e
         ))),
     }
 }
 
-fn load_private_key(private_key_path: &Path) -> Result<PrivateKeyDer, WebMQError> {
-    match get_file_buffer(private_key_path).map(|buf| PrivateKeyDer::Pkcs1(buf.into())) {
-        Ok(a) => Ok(a),
+fn load_revocation_lists(
+    path: &Path,
+) -> Result<Vec<CertificateRevocationListDer<'static>>, WebMQError> {
+    let buffer = match get_file_buffer(path) {
+        Ok(buf) => buf,
+        Err(e) => {
+            return Err(WebMQError::Config(format!(
+                "Couldn't load revocation list: {}",
+                e
+            )));
+        }
+    };
+
+    if !is_pem(&buffer) {
+        return Ok(vec![CertificateRevocationListDer::from(buffer)]);
+    }
+
+    match CertificateRevocationListDer::pem_slice_iter(&buffer).collect() {
+        Ok(crls) => Ok(crls),
         Err(e) => Err(WebMQError::Config(format!(
-            "Couldn't load private key: {}",
+            "Couldn't parse revocation list: {}",
             e
         ))),
     }
 }
+
+/// Loads a PKCS#1, PKCS#8 or SEC1 private key from a PEM or DER file.
+fn load_private_key(private_key_path: &Path) -> Result<PrivateKeyDer<'static>, WebMQError> {
+    let buffer = match get_file_buffer(private_key_path) {
+        Ok(buf) => buf,
+        Err(e) => {
+            return Err(WebMQError::Config(format!(
+                "Couldn't load private key: {}",
+                e
+            )));
+        }
+    };
+
+    let private_key = if is_pem(&buffer) {
+        PrivateKeyDer::from_pem_slice(&buffer).map_err(|e| e.to_string())
+    } else {
+        PrivateKeyDer::try_from(buffer.as_slice())
+            .map(|pkey| pkey.clone_key())
+            .map_err(|e| e.to_string())
+    };
+
+    private_key.map_err(|e| WebMQError::Config(format!("Couldn't parse private key: {}", e)))
+}
+
+/// Turns `private_key` into a signing key, making sure it uses the algorithm
+/// the configuration asks for.
+fn load_signing_key(
+    private_key: PrivateKeyDer<'static>,
+    expected: KeyAlgorithm,
+) -> Result<Arc<dyn SigningKey>, WebMQError> {
+    let Some(provider) = CryptoProvider::get_default() else {
+        return Err(WebMQError::TLS(
+            "No cryptography provider installed".to_string(),
+        ));
+    };
+
+    let signing_key = match provider.key_provider.load_private_key(private_key) {
+        Ok(signing_key) => signing_key,
+        Err(e) => {
+            return Err(WebMQError::Config(format!(
+                "Unsupported private key: {}",
+                e
+            )));
+        }
+    };
+
+    let algorithm = signing_key.algorithm();
+    let matches = match expected {
+        KeyAlgorithm::Auto => true,
+        KeyAlgorithm::Rsa => algorithm == SignatureAlgorithm::RSA,
+        KeyAlgorithm::Ecdsa => algorithm == SignatureAlgorithm::ECDSA,
+        KeyAlgorithm::Ed25519 => algorithm == SignatureAlgorithm::ED25519,
+    };
+
+    if !matches {
+        return Err(WebMQError::Config(format!(
+            "Private key uses {algorithm:?} but {expected:?} is configured"
+        )));
+    }
+
+    Ok(signing_key)
+}
+
+fn is_pem(buffer: &[u8]) -> bool {
+    buffer.trim_ascii_start().starts_with(PEM_PREFIX)
+}
@@ -0,0 +1,58 @@
+use std::net::IpAddr;
+
+use log::warn;
+use tls_listener::rustls::rustls::{pki_types::CertificateDer, server::ServerConnection};
+use x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer};
+
+use crate::core::models::identity::ClientIdentity;
+
+/// Returns the identity from the certificate the client authenticated with,
+/// if it presented one.
+pub fn client_identity(connection: &ServerConnection) -> Option<ClientIdentity> {
+    let certificate = connection.peer_certificates()?.first()?;
+    match parse_identity(certificate) {
+        Ok(identity) => Some(identity),
+        Err(e) => {
+            warn!("Couldn't read client certificate: {e}");
+            None
+        }
+    }
+}
+
+fn parse_identity(certificate: &CertificateDer<'_>) -> Result<ClientIdentity, String> {
+    let (_, certificate) = X509Certificate::from_der(certificate).map_err(|e| e.to_string())?;
+
+    let alternative_names = match certificate.subject_alternative_name() {
+        Ok(Some(extension)) => extension
+            .value
+            .general_names
+            .iter()
+            .filter_map(alternative_name)
+            .collect(),
+        Ok(None) => vec![],
+        Err(e) => return Err(e.to_string()),
+    };
+
+    Ok(ClientIdentity {
+        subject: certificate.subject().to_string(),
+        alternative_names,
+    })
+}
+
+fn alternative_name(name: &GeneralName<'_>) -> Option<String> {
+    match name {
+        GeneralName::DNSName(name) => Some(format!("DNS:{name}")),
+        GeneralName::RFC822Name(name) => Some(format!("email:{name}")),
+        GeneralName::URI(uri) => Some(format!("URI:{uri}")),
+        GeneralName::IPAddress(bytes) => ip_address(bytes).map(|ip| format!("IP:{ip}")),
+        _ => None,
+    }
+}
+
+fn ip_address(bytes: &[u8]) -> Option<IpAddr> {
+    if let Ok(octets) = <[u8; 4]>::try_from(bytes) {
+        return Some(IpAddr::from(octets));
+    }
+
+    <[u8; 16]>::try_from(bytes).ok().map(IpAddr::from)
+}
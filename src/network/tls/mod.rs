@@ -1 +1,3 @@
 pub mod acceptor;
+pub mod identity;
+pub mod resolver;
@@ -0,0 +1,202 @@
+use std::{
+    collections::HashMap,
+    fs::metadata,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use log::{info, warn};
+use rustls::{
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+};
+
+use crate::core::{
+    config::tls::{KeyAlgorithm, TlsSettings},
+    errors::WebMQError,
+};
+
+use super::acceptor::load_certified_key;
+
+type Modified = Vec<(Option<SystemTime>, Option<SystemTime>)>;
+
+/// A certificate along with the files it was loaded from.
+#[derive(Debug)]
+struct ReloadableKey {
+    certificate: PathBuf,
+    private_key: PathBuf,
+    algorithm: KeyAlgorithm,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+/// Picks the certificate to present by the server name the client asks for,
+/// and swaps certificates for new ones whenever their files change on disk or
+/// the process receives SIGHUP. Handshakes which already started keep the
+/// certificate they picked.
+#[derive(Debug)]
+pub struct ReloadingCertResolver {
+    default: ReloadableKey,
+    server_names: HashMap<String, ReloadableKey>,
+}
+
+impl ReloadableKey {
+    fn load(certificate: &str, private_key: &str, algorithm: KeyAlgorithm) -> Result<Self, WebMQError> {
+        let certificate = PathBuf::from(certificate);
+        let private_key = PathBuf::from(private_key);
+        let certified_key = load_certified_key(&certificate, &private_key, algorithm)?;
+
+        Ok(Self {
+            certificate,
+            private_key,
+            algorithm,
+            current: RwLock::new(Arc::new(certified_key)),
+        })
+    }
+
+    fn reload(&self) -> Result<(), WebMQError> {
+        let certified_key = load_certified_key(&self.certificate, &self.private_key, self.algorithm)?;
+        match self.current.write() {
+            Ok(mut current) => {
+                *current = Arc::new(certified_key);
+                Ok(())
+            }
+            Err(_) => Err(WebMQError::Unrecoverable),
+        }
+    }
+
+    fn get(&self) -> Option<Arc<CertifiedKey>> {
+        self.current.read().ok().map(|current| current.clone())
+    }
+
+    fn modified(&self) -> (Option<SystemTime>, Option<SystemTime>) {
+        let modified = |path: &PathBuf| metadata(path).and_then(|meta| meta.modified()).ok();
+        (modified(&self.certificate), modified(&self.private_key))
+    }
+}
+
+impl ReloadingCertResolver {
+    pub fn new(tls_config: &TlsSettings) -> Result<Self, WebMQError> {
+        let default = ReloadableKey::load(
+            &tls_config.certificate,
+            &tls_config.private_key,
+            tls_config.algorithm,
+        )?;
+
+        let mut server_names = HashMap::new();
+        for (name, settings) in &tls_config.server_names {
+            let key = ReloadableKey::load(&settings.certificate, &settings.private_key, settings.algorithm)?;
+            info!("Serving certificate {} for {name}", settings.certificate);
+            server_names.insert(name.to_ascii_lowercase(), key);
+        }
+
+        Ok(Self {
+            default,
+            server_names,
+        })
+    }
+
+    /// Starts reloading certificates on SIGHUP, and every time one of them
+    /// changes when an `interval` to check for changes at is given.
+    pub fn watch(self: Arc<Self>, interval: Option<Duration>) {
+        tokio::task::spawn(async move {
+            let mut hangups = Hangups::new();
+            let mut modified = self.modified();
+            loop {
+                let forced = tokio::select! {
+                    _ = hangups.recv() => {
+                        info!("Received SIGHUP, reloading certificates");
+                        true
+                    }
+                    _ = sleep(interval) => false,
+                };
+
+                // Checked before reading the files, so a write racing with the
+                // reload is picked up on the next check.
+                let seen = self.modified();
+                for ((key, before), after) in self.keys().zip(&mut modified).zip(seen) {
+                    if !forced && *before == after {
+                        continue;
+                    }
+
+                    info!("Reloading certificate {}", key.certificate.to_string_lossy());
+                    match key.reload() {
+                        Ok(()) => *before = after,
+                        Err(e) => warn!("Keeping the current certificate: {e}"),
+                    }
+                }
+            }
+        });
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &ReloadableKey> {
+        std::iter::once(&self.default).chain(self.server_names.values())
+    }
+
+    fn modified(&self) -> Modified {
+        self.keys().map(ReloadableKey::modified).collect()
+    }
+
+    fn find(&self, server_name: &str) -> Option<&ReloadableKey> {
+        let server_name = server_name.to_ascii_lowercase();
+        if let Some(key) = self.server_names.get(&server_name) {
+            return Some(key);
+        }
+
+        let (_, parent) = server_name.split_once('.')?;
+        self.server_names.get(&format!("*.{parent}"))
+    }
+}
+
+impl ResolvesServerCert for ReloadingCertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        client_hello
+            .server_name()
+            .and_then(|server_name| self.find(server_name))
+            .unwrap_or(&self.default)
+            .get()
+    }
+}
+
+async fn sleep(interval: Option<Duration>) {
+    match interval {
+        Some(interval) => tokio::time::sleep(interval).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// SIGHUP notifications. Never fires where there are no such signals.
+struct Hangups {
+    #[cfg(unix)]
+    signal: Option<tokio::signal::unix::Signal>,
+}
+
+impl Hangups {
+    fn new() -> Self {
+        #[cfg(unix)]
+        {
+            use tokio::signal::unix::{SignalKind, signal};
+
+            let signal = match signal(SignalKind::hangup()) {
+                Ok(signal) => Some(signal),
+                Err(e) => {
+                    warn!("Couldn't listen for SIGHUP: {e}");
+                    None
+                }
+            };
+            Self { signal }
+        }
+        #[cfg(not(unix))]
+        Self {}
+    }
+
+    async fn recv(&mut self) {
+        #[cfg(unix)]
+        if let Some(signal) = &mut self.signal {
+            signal.recv().await;
+            return;
+        }
+
+        std::future::pending::<()>().await
+    }
+}
@@ -1 +1,3 @@
+pub mod duration;
 pub mod file;
+pub mod pattern;
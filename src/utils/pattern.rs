@@ -0,0 +1,58 @@
+/// Matches `text` against a pattern where `*` stands for any number of
+/// characters and `?` for exactly one.
+pub fn matches_pattern(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and retry.
+                Some((star, matched)) => {
+                    backtrack = Some((star, matched + 1));
+                    p = star + 1;
+                    t = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_literals_and_wildcards() {
+        assert!(matches_pattern("orders", "orders"));
+        assert!(!matches_pattern("orders", "order"));
+        assert!(matches_pattern("order?", "orders"));
+        assert!(!matches_pattern("order?", "order"));
+        assert!(matches_pattern("*", ""));
+        assert!(matches_pattern("orders.*", "orders."));
+        assert!(matches_pattern("*.eu", "orders.eu"));
+        assert!(!matches_pattern("*.eu", "orders.us"));
+    }
+
+    #[test]
+    fn backtracks_into_earlier_stars() {
+        assert!(matches_pattern("*a*b", "xaxbxab"));
+        assert!(matches_pattern("a*b?c", "abbbxc"));
+        assert!(matches_pattern("**a", "bba"));
+        assert!(!matches_pattern("*a*b", "xaxbxa"));
+        assert!(!matches_pattern("a*?", "a"));
+    }
+}